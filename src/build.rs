@@ -1,11 +1,18 @@
+use crate::cargo_info::{self, Dependency, ImportCheck};
 use crate::error::{Error, Result};
-use crate::update_std::target_json_path;
+use crate::{cache, inspect};
+use crate::lock::LockFile;
+use crate::update_std::project_target_json_path;
 use cargo_metadata::Message;
 use linkle::format::nxo::NxoFile;
+use owo_colors::OwoColorize;
+use std::collections::HashSet;
 use std::env;
+use std::fs;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use structopt::StructOpt;
 
 pub(crate) fn get_rustup_home() -> Result<PathBuf> {
     env::var("RUSTUP_HOME").map(PathBuf::from).or_else(|_| {
@@ -55,8 +62,77 @@ impl CargoCommand {
     }
 }
 
-pub fn check(json: bool) -> Result<()> {
-    cargo_run_command(CargoCommand::Check, Vec::new(), json).map(|_| ())
+// Cargo options shared by every subcommand which ends up invoking cargo
+#[derive(StructOpt)]
+pub struct CargoOpts {
+    #[structopt(short, long, about = "Package to build")]
+    package: Option<String>,
+
+    #[structopt(long, about = "Directory for all generated artifacts")]
+    target_dir: Option<String>,
+
+    #[structopt(long, about = "Run without accessing the network")]
+    pub offline: bool,
+
+    #[structopt(long, about = "Require Cargo.lock is up to date")]
+    locked: bool,
+
+    #[structopt(long, about = "Build artifacts with the specified profile")]
+    pub profile: Option<String>,
+
+    #[structopt(short, long, about = "Number of parallel jobs")]
+    jobs: Option<String>,
+
+    #[structopt(short = "Z", number_of_values = 1, about = "Unstable (nightly-only) flags to Cargo")]
+    unstable_flags: Vec<String>,
+}
+
+impl CargoOpts {
+    /// Convert into arguments for cargo, followed by any passed through after `--`
+    pub fn into_args(self, passthrough: Vec<String>) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(package) = self.package {
+            args.push("--package".into());
+            args.push(package);
+        }
+
+        if let Some(target_dir) = self.target_dir {
+            args.push("--target-dir".into());
+            args.push(target_dir);
+        }
+
+        if self.offline {
+            args.push("--offline".into());
+        }
+
+        if self.locked {
+            args.push("--locked".into());
+        }
+
+        if let Some(profile) = self.profile {
+            args.push("--profile".into());
+            args.push(profile);
+        }
+
+        if let Some(jobs) = self.jobs {
+            args.push("--jobs".into());
+            args.push(jobs);
+        }
+
+        for flag in self.unstable_flags {
+            args.push("-Z".into());
+            args.push(flag);
+        }
+
+        args.extend(passthrough);
+
+        args
+    }
+}
+
+pub fn check(args: Vec<String>, json: bool) -> Result<()> {
+    cargo_run_command(CargoCommand::Check, args, json).map(|_| ())
 }
 
 pub fn clippy(args: Vec<String>, json: bool) -> Result<()> {
@@ -74,7 +150,7 @@ pub fn build_get_artifact(args: Vec<String>) -> Result<PathBuf> {
                 None
             }
         })
-        .last()
+        .next_back()
         .ok_or(Error::FailParseCargoStream)?;
 
     Ok(last_artifact.filenames[0].clone())
@@ -85,9 +161,11 @@ fn cargo_run_command(
     args: Vec<String>,
     print_cargo_messages: bool,
 ) -> Result<Vec<Message>> {
-    crate::update_std::check_std_installed()?;
+    let toolchain = crate::toolchain::project_toolchain();
+    crate::update_std::check_std_installed(&toolchain)?;
 
-    let target_json_path = target_json_path();
+    let metadata = cargo_info::get_metadata()?;
+    let target_json_path = project_target_json_path(&metadata)?;
 
     // Ensure rust-lld is added to the PATH on Windows
     if Command::new("rust-lld")
@@ -109,12 +187,12 @@ fn cargo_run_command(
         env::set_var("PATH", &new_path);
     }
 
-    // rustup run skyline-v3 SKYLINE_ADD_NRO_HEADER=1 RUSTFLAGS="--cfg skyline_std_v3" cargo build --target ~/.cargo/skyline/aarch64-skyline-switch.json -Z build-std=core,alloc,std,panic_abort
+    // rustup run <toolchain> SKYLINE_ADD_NRO_HEADER=1 RUSTFLAGS="--cfg skyline_std_v3" cargo build --target ~/.cargo/skyline/aarch64-skyline-switch.json -Z build-std=core,alloc,std,panic_abort
     let mut command = Command::new("rustup")
         .arg("run")
-        .arg("skyline-v3")
+        .arg(&metadata.toolchain)
         .arg("cargo")
-        .args(&[
+        .args([
             command.to_str(),
             "--message-format=json-diagnostic-rendered-ansi",
             "--color",
@@ -122,7 +200,7 @@ fn cargo_run_command(
             "--target",
         ])
         .arg(&target_json_path)
-        .args(&["-Z", "build-std=core,alloc,std,panic_abort"])
+        .args(["-Z", "build-std=core,alloc,std,panic_abort"])
         .args(args)
         .env("SKYLINE_ADD_NRO_HEADER", "1")
         .env("RUSTFLAGS", "--cfg skyline_std_v3")
@@ -152,9 +230,11 @@ fn cargo_run_command(
             }
         })
         .collect::<std::result::Result<Vec<_>, _>>()
-        .map_err(|_| Error::FailParseCargoStream)?;
+        .map_err(|_| Error::FailParseCargoStream);
 
+    // Always wait on cargo, even if its output couldn't be parsed, so it isn't left a zombie
     let exit_status = command.wait().unwrap();
+    let cargo_messages = cargo_messages?;
 
     if !exit_status.success() {
         Err(Error::ExitStatus(exit_status.code().unwrap_or(1)))
@@ -163,9 +243,172 @@ fn cargo_run_command(
     }
 }
 
+/// Download Skyline and collect the exports of its subsdk, checking it against Skyline.lock so
+/// that imports are checked against the Skyline which gets installed
+fn download_skyline_exports(
+    release: &cargo_info::SkylineRelease,
+    lock: &mut LockFile,
+) -> Result<Vec<String>> {
+    let url = lock.skyline_url(&release.url())?;
+    let data = cache::download(&url)?;
+    lock.verify_skyline(
+        &url,
+        release.version.as_deref(),
+        release.sha256.as_deref(),
+        &data,
+    )?;
+
+    let subsdk = crate::package::exefs_from_zip(data)?.subsdk1;
+
+    Ok(inspect::parse_binary_data("subsdk9".into(), &subsdk)?
+        .exports
+        .into_iter()
+        .map(|sym| sym.name)
+        .collect())
+}
+
+/// Download a plugin dependency and collect the exports of every binary it installs, using the
+/// release recorded in Skyline.lock so that imports are checked against what gets installed
+fn download_exports(dep: &Dependency, lock: &mut LockFile) -> Result<Vec<String>> {
+    Ok(crate::dependency::download(dep, Some(lock))?
+        .into_iter()
+        .filter_map(|file| inspect::parse_binary_data(file.install_path.into(), &file.data).ok())
+        .flat_map(|info| info.exports)
+        .map(|sym| sym.name)
+        .collect())
+}
+
+/// Read a list of exports from either a binary or a text file with one symbol per line
+fn read_known_exports(path: &Path) -> Result<Vec<String>> {
+    if let Ok(info) = inspect::parse_binary(path) {
+        return Ok(info.exports.into_iter().map(|sym| sym.name).collect());
+    }
+
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// The C library and C++ runtime exports of nnSdk which plugins import through std
+const SDK_EXPORTS: &str = include_str!("sdk_exports.txt");
+
+/// Mangled `nn::` functions and const methods
+const SDK_PREFIXES: [&str; 2] = ["_ZN2nn", "_ZNK2nn"];
+
+/// Symbols provided by the game's copy of the SDK, assumed to resolve when no exports for the
+/// game have been given
+fn is_sdk_symbol(sdk_exports: &HashSet<&str>, name: &str) -> bool {
+    SDK_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) || sdk_exports.contains(name)
+}
+
+/// Compare the undefined dynamic symbols of the plugin against the exports of Skyline and every
+/// plugin dependency, as anything left over will only surface as a crash on boot
+fn check_imports(artifact: &Path) -> Result<()> {
+    let metadata = cargo_info::get_metadata()?;
+
+    let level = match metadata.check_imports {
+        Some(level) => level,
+        None => return Ok(()),
+    };
+
+    let imports = inspect::parse_binary(artifact)?.imports;
+    if imports.is_empty() {
+        return Ok(());
+    }
+
+    println!("Checking imports against Skyline and plugin dependencies...");
+
+    let mut known = HashSet::new();
+
+    // The lock file isn't saved, as only install and package record new entries
+    let mut lock = LockFile::load(&metadata.workspace_root, false)?;
+    let mut incomplete = false;
+
+    match download_skyline_exports(&metadata.skyline_release, &mut lock) {
+        Ok(exports) => known.extend(exports),
+        Err(_) => {
+            eprintln!(
+                "{}: could not get the exports of Skyline, imports from it can't be checked",
+                "WARN".yellow().bold()
+            );
+            incomplete = true;
+        }
+    }
+
+    for dep in &metadata.plugin_dependencies {
+        match download_exports(dep, &mut lock) {
+            Ok(exports) => known.extend(exports),
+            Err(_) => {
+                eprintln!(
+                    "{}: could not get the exports of '{}', imports from it can't be checked",
+                    "WARN".yellow().bold(),
+                    dep.name
+                );
+                incomplete = true;
+            }
+        }
+    }
+
+    for path in &metadata.known_exports {
+        known.extend(read_known_exports(path)?);
+    }
+
+    let assume_sdk = metadata.known_exports.is_empty();
+    let sdk_exports: HashSet<_> = SDK_EXPORTS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let unresolved: Vec<_> = imports
+        .iter()
+        .filter(|name| {
+            !(known.contains(*name) || assume_sdk && is_sdk_symbol(&sdk_exports, name))
+        })
+        .collect();
+
+    if unresolved.is_empty() {
+        println!("{}", "All imports resolved!".green());
+        return Ok(());
+    }
+
+    // Imports may be unresolved only because a dependency couldn't be downloaded
+    let level = if incomplete { ImportCheck::Warn } else { level };
+
+    let label = match level {
+        ImportCheck::Warn => "WARN".yellow().bold().to_string(),
+        ImportCheck::Deny => "ERROR".red().bold().to_string(),
+    };
+
+    eprintln!(
+        "{}: {} import(s) are not exported by Skyline or any plugin dependency:",
+        label,
+        unresolved.len()
+    );
+    for name in &unresolved {
+        eprintln!("   └ {:#}", rustc_demangle::demangle(name));
+    }
+    if assume_sdk {
+        eprintln!(
+            "   └ {}: nn:: and C library symbols were assumed to come from the game's SDK, list the game's exports under `known-exports` to check them too",
+            "NOTE".bright_blue().bold()
+        );
+    }
+
+    match level {
+        ImportCheck::Warn => Ok(()),
+        ImportCheck::Deny => Err(Error::UnresolvedImports(unresolved.len())),
+    }
+}
+
 pub fn build_get_nro(args: Vec<String>) -> Result<PathBuf> {
     let artifact = build_get_artifact(args)?;
 
+    check_imports(&artifact)?;
+
     let nro_path = artifact.with_extension("nro");
 
     NxoFile::from_elf(artifact.to_str().ok_or(Error::FailWriteNro)?)?.write_nro(
@@ -181,6 +424,8 @@ pub fn build_get_nro(args: Vec<String>) -> Result<PathBuf> {
 pub fn build_get_nso(args: Vec<String>) -> Result<PathBuf> {
     let artifact = build_get_artifact(args)?;
 
+    check_imports(&artifact)?;
+
     let nso_path = artifact.with_extension("nso");
 
     NxoFile::from_elf(artifact.to_str().ok_or(Error::FailWriteNro)?)?
@@ -0,0 +1,204 @@
+use crate::error::{Error, Result};
+use crate::update_std::{get_cargo_skyline_dir, PathExt};
+use owo_colors::OwoColorize;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use structopt::StructOpt;
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+#[derive(StructOpt)]
+pub enum CacheCommand {
+    #[structopt(about = "List the downloads currently cached")]
+    List,
+    #[structopt(about = "Remove all cached downloads")]
+    Clean,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CacheEntry {
+    sha256: String,
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+type CacheIndex = BTreeMap<String, CacheEntry>;
+
+fn cache_dir() -> PathBuf {
+    get_cargo_skyline_dir().push_join("cache").ensure_exists()
+}
+
+fn index_path() -> PathBuf {
+    cache_dir().push_join("index.json")
+}
+
+fn blob_path(sha256: &str) -> PathBuf {
+    cache_dir().push_join("blobs").ensure_exists().push_join(sha256)
+}
+
+fn read_index() -> CacheIndex {
+    fs::read(index_path())
+        .ok()
+        .and_then(|index| serde_json::from_slice(&index).ok())
+        .unwrap_or_default()
+}
+
+fn write_index(index: &CacheIndex) -> Result<()> {
+    fs::write(index_path(), serde_json::to_string_pretty(index).unwrap())?;
+
+    Ok(())
+}
+
+pub fn set_offline() {
+    OFFLINE.store(true, Ordering::Relaxed);
+}
+
+/// Whether downloads should only be served from the cache, either due to `--offline` or cargo's
+/// own `CARGO_NET_OFFLINE`
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+        || std::env::var("CARGO_NET_OFFLINE")
+            .map(|offline| offline == "true" || offline == "1")
+            .unwrap_or(false)
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn read_cached(entry: &CacheEntry) -> Option<Vec<u8>> {
+    let data = fs::read(blob_path(&entry.sha256)).ok()?;
+
+    // Treat blobs which no longer match their hash as missing
+    (sha256_hex(&data) == entry.sha256).then_some(data)
+}
+
+/// Download the given url, revalidating against the cached copy using its ETag/Last-Modified
+/// and falling back to the cache when offline
+pub fn download(url: &str) -> Result<Vec<u8>> {
+    let mut index = read_index();
+    let entry = index.get(url).cloned();
+    let cached = entry.as_ref().and_then(read_cached);
+
+    if is_offline() {
+        return cached.ok_or_else(|| Error::NotCached(url.to_owned()));
+    }
+
+    let mut request = reqwest::blocking::Client::new().get(url);
+
+    if let (Some(entry), Some(_)) = (&entry, &cached) {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        if let Some(last_modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = match request.send() {
+        Ok(response) => response,
+        Err(_) => {
+            return match cached {
+                Some(cached) => {
+                    eprintln!(
+                        "{}: failed to download '{}', using cached copy",
+                        "WARN".yellow().bold(),
+                        url
+                    );
+                    Ok(cached)
+                }
+                None => Err(Error::DownloadError),
+            };
+        }
+    };
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            return Ok(cached);
+        }
+    }
+
+    if !response.status().is_success() {
+        return Err(Error::DownloadError);
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from)
+    };
+
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+
+    let data = response.bytes().map_err(|_| Error::DownloadError)?.to_vec();
+    let sha256 = sha256_hex(&data);
+
+    fs::write(blob_path(&sha256), &data)?;
+
+    let previous = index.insert(
+        url.to_owned(),
+        CacheEntry {
+            sha256,
+            size: data.len() as u64,
+            etag,
+            last_modified,
+        },
+    );
+
+    // Drop the blob for the previous contents unless another url still refers to it
+    if let Some(previous) = previous {
+        if !index.values().any(|entry| entry.sha256 == previous.sha256) {
+            let _ = fs::remove_file(blob_path(&previous.sha256));
+        }
+    }
+
+    write_index(&index)?;
+
+    Ok(data)
+}
+
+fn list() -> Result<()> {
+    let index = read_index();
+
+    if index.is_empty() {
+        println!("No downloads cached");
+        return Ok(());
+    }
+
+    for (url, entry) in &index {
+        println!(
+            "{} {:>10} {}",
+            &entry.sha256[..12],
+            entry.size,
+            url.bright_blue()
+        );
+    }
+
+    Ok(())
+}
+
+fn clean() -> Result<()> {
+    fs::remove_dir_all(cache_dir())?;
+
+    println!("Removed all cached downloads");
+
+    Ok(())
+}
+
+pub fn cache(command: CacheCommand) -> Result<()> {
+    match command {
+        CacheCommand::List => list(),
+        CacheCommand::Clean => clean(),
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use crate::error::Result;
@@ -6,17 +7,87 @@ use serde::Deserialize;
 #[derive(Deserialize)]
 pub struct Metadata {
     pub name: String,
+    pub version: String,
     pub title_id: Option<String>,
+    pub title_ids: Vec<String>,
     pub npdm_path: Option<String>,
     pub subsdk_name: Option<String>,
     pub plugin_dependencies: Vec<Dependency>,
     pub package_resources: Vec<PackageResource>,
+    pub check_imports: Option<ImportCheck>,
+    pub known_exports: Vec<PathBuf>,
+    pub target_dir: PathBuf,
+    pub workspace_root: PathBuf,
+    pub target_json_path: Option<PathBuf>,
+    pub target_overrides: Option<serde_json::Value>,
+    pub linker_script_path: Option<PathBuf>,
+    pub extra_linker_script_path: Option<PathBuf>,
+    pub skyline_release: SkylineRelease,
+    pub toolchain: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct SkylineRelease {
+    pub url: Option<String>,
+    pub version: Option<String>,
+    pub sha256: Option<String>,
+}
+
+impl SkylineRelease {
+    /// The url to download Skyline from, with an explicit url taking priority over a version
+    pub fn url(&self) -> String {
+        match (&self.url, &self.version) {
+            (Some(url), _) => url.clone(),
+            (None, Some(version)) => format!(
+                "https://github.com/skyline-dev/skyline/releases/download/{}/skyline.zip",
+                version
+            ),
+            (None, None) => crate::installer::SKYLINE_URL.to_owned(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportCheck {
+    Warn,
+    Deny,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Dependency {
     pub name: String,
-    pub url: String,
+    pub url: Option<String>,
+    pub repo: Option<String>,
+    pub asset: Option<String>,
+    pub tag: Option<String>,
+    pub version: Option<String>,
+    pub sha256: Option<String>,
+    pub path: Option<String>,
+    pub extract: BTreeMap<String, String>,
+}
+
+impl Dependency {
+    /// Whether the download is a zip to be extracted rather than a single file to install. Url
+    /// dependencies have always been installed as-is, so are only extracted given an `extract` map.
+    pub fn is_archive(&self) -> bool {
+        let is_release_zip = self.url.is_none()
+            && self
+                .asset
+                .as_deref()
+                .unwrap_or(&self.name)
+                .ends_with(".zip");
+
+        !self.extract.is_empty() || is_release_zip
+    }
+
+    /// Where to install a single-file dependency, as a `rom:/` or `sd:/` path
+    pub fn install_path(&self) -> String {
+        match &self.path {
+            Some(path) if path.ends_with('/') => format!("{}{}", path, self.name),
+            Some(path) => path.clone(),
+            None => format!("rom:/skyline/plugins/{}", self.name),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -25,36 +96,84 @@ pub struct PackageResource {
     pub package_path: PathBuf,
 }
 
-fn get_title_id(md: &serde_json::Value) -> Option<String> {
+/// `titleid` can either be a single title ID or a list of them, such as for each region
+fn get_title_ids(md: &serde_json::Value) -> Option<Vec<String>> {
+    let title_id = md.get("skyline")?.as_object()?.get("titleid")?;
+
+    match title_id.as_array() {
+        Some(title_ids) => Some(
+            title_ids
+                .iter()
+                .filter_map(|title_id| Some(title_id.as_str()?.into()))
+                .collect(),
+        ),
+        None => Some(vec![title_id.as_str()?.into()]),
+    }
+}
+
+fn get_npdm_path(md: &serde_json::Value) -> Option<String> {
     Some(
         md.get("skyline")?
             .as_object()?
-            .get("titleid")?
+            .get("custom-npdm")?
             .as_str()?
             .into(),
     )
 }
 
-fn get_npdm_path(md: &serde_json::Value) -> Option<String> {
+fn get_subsdk_name(md: &serde_json::Value) -> Option<String> {
     Some(
         md.get("skyline")?
             .as_object()?
-            .get("custom-npdm")?
+            .get("subsdk-name")?
             .as_str()?
             .into(),
     )
 }
 
-fn get_subsdk_name(md: &serde_json::Value) -> Option<String> {
+fn get_skyline_path(md: &serde_json::Value, key: &str) -> Option<PathBuf> {
     Some(
         md.get("skyline")?
             .as_object()?
-            .get("subsdk-name")?
+            .get(key)?
             .as_str()?
             .into(),
     )
 }
 
+fn get_target_overrides(md: &serde_json::Value) -> Option<serde_json::Value> {
+    md.get("skyline")?
+        .as_object()?
+        .get("target-overrides")
+        .filter(|overrides| overrides.is_object())
+        .cloned()
+}
+
+fn get_check_imports(md: &serde_json::Value) -> Option<ImportCheck> {
+    let level = md
+        .get("skyline")?
+        .as_object()?
+        .get("check-imports")?;
+
+    match level.as_str() {
+        Some("deny") => Some(ImportCheck::Deny),
+        Some("warn") => Some(ImportCheck::Warn),
+        _ => level.as_bool()?.then_some(ImportCheck::Warn),
+    }
+}
+
+fn get_known_exports(md: &serde_json::Value) -> Option<Vec<PathBuf>> {
+    Some(
+        md.get("skyline")?
+            .as_object()?
+            .get("known-exports")?
+            .as_array()?
+            .iter()
+            .filter_map(|path| Some(path.as_str()?.into()))
+            .collect(),
+    )
+}
+
 fn get_dep_urls(md: &serde_json::Value) -> Option<Vec<Dependency>> {
     Some(
         md.get("skyline")?
@@ -65,13 +184,43 @@ fn get_dep_urls(md: &serde_json::Value) -> Option<Vec<Dependency>> {
             .map(|x| {
                 let dep = x.as_object().unwrap();
                 let name = dep.get("name").unwrap().as_str().unwrap().into();
-                let url = dep.get("url").unwrap().as_str().unwrap().into();
-                Dependency { name, url }
+                let get = |key| dep.get(key).and_then(|x| Some(x.as_str()?.into()));
+                let extract = dep
+                    .get("extract")
+                    .and_then(|x| x.as_object())
+                    .map(|extract| {
+                        extract
+                            .iter()
+                            .filter_map(|(from, to)| Some((from.clone(), to.as_str()?.into())))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Dependency {
+                    name,
+                    url: get("url"),
+                    repo: get("repo"),
+                    asset: get("asset"),
+                    tag: get("tag"),
+                    version: get("version"),
+                    sha256: get("sha256"),
+                    path: get("path"),
+                    extract,
+                }
             })
             .collect(),
     )
 }
 
+fn get_skyline_release(md: &serde_json::Value) -> Option<SkylineRelease> {
+    serde_json::from_value(
+        md.get("skyline")?
+            .as_object()?
+            .get("skyline-release")?
+            .clone(),
+    )
+    .ok()
+}
+
 fn get_package_deps(md: &serde_json::Value) -> Option<Vec<PackageResource>> {
     Some(
         md.get("skyline")?
@@ -89,13 +238,48 @@ fn get_package_deps(md: &serde_json::Value) -> Option<Vec<PackageResource>> {
     )
 }
 
+fn get_toml_pinned_toolchain(manifest: &toml::Value) -> Option<String> {
+    ["package", "workspace"].iter().find_map(|table| {
+        manifest
+            .get(table)?
+            .get("metadata")?
+            .get("skyline")?
+            .get("toolchain")?
+            .as_str()
+            .map(String::from)
+    })
+}
+
+/// Get the toolchain pinned by `toolchain` in the nearest Cargo.toml declaring one, up to the root
+/// of the workspace. This is read directly from the manifests as it's needed before `cargo
+/// metadata` can be run with the skyline toolchain.
+pub fn get_pinned_toolchain() -> Option<String> {
+    let workspace_root = MetadataCommand::new()
+        .no_deps()
+        .exec()
+        .ok()
+        .and_then(|metadata| metadata.workspace_root.canonicalize().ok())?;
+    let current_dir = std::env::current_dir().ok()?.canonicalize().ok()?;
+
+    current_dir
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&workspace_root))
+        .find_map(|dir| {
+            let manifest = std::fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+
+            get_toml_pinned_toolchain(&toml::from_str(&manifest).ok()?)
+        })
+}
+
 use cargo_metadata::MetadataCommand;
 
 pub fn get_metadata() -> Result<Metadata> {
+    let toolchain = crate::toolchain::project_toolchain();
+
     let output = MetadataCommand::new()
         //.other_options(["--target".to_string(), "aarch64-skyline-switch".to_string()])
         .cargo_command()?
-        .env("RUSTUP_TOOLCHAIN", "skyline-v3")
+        .env("RUSTUP_TOOLCHAIN", &toolchain)
         .output()?;
 
     if !output.status.success() {
@@ -122,10 +306,21 @@ pub fn get_metadata() -> Result<Metadata> {
         .unwrap()
         .to_string();
 
-    let title_id = metadata
+    let version = metadata
         .packages
         .iter()
-        .fold(None, |x, y| x.or_else(|| get_title_id(&y.metadata)));
+        .find(|package| Some(&package.id) == metadata.workspace_members.first())
+        .map(|package| package.version.to_string())
+        .unwrap_or_default();
+
+    let title_ids = metadata
+        .packages
+        .iter()
+        .fold(None, |x, y| x.or_else(|| get_title_ids(&y.metadata)))
+        .unwrap_or_default();
+
+    // Everything but packaging only works with the first title ID
+    let title_id = title_ids.first().cloned();
 
     let npdm_path = metadata
         .packages
@@ -147,12 +342,63 @@ pub fn get_metadata() -> Result<Metadata> {
         x
     });
 
+    let check_imports = metadata
+        .packages
+        .iter()
+        .fold(None, |x, y| x.or_else(|| get_check_imports(&y.metadata)));
+
+    let known_exports = metadata.packages.iter().fold(vec![], |mut x, y| {
+        x.append(&mut get_known_exports(&y.metadata).unwrap_or_default());
+        x
+    });
+
+    // Paths are relative to the Cargo.toml they're given in, not wherever cargo-skyline is run
+    let find_path = |key| {
+        metadata.packages.iter().fold(None, |x, y| {
+            x.or_else(|| {
+                let path = get_skyline_path(&y.metadata, key)?;
+
+                Some(match y.manifest_path.parent() {
+                    Some(dir) => dir.join(path),
+                    None => path,
+                })
+            })
+        })
+    };
+
+    let target_json_path = find_path("custom-target-json");
+    let linker_script_path = find_path("custom-linker-script");
+    let extra_linker_script_path = find_path("extra-linker-script");
+
+    let target_overrides = metadata
+        .packages
+        .iter()
+        .fold(None, |x, y| x.or_else(|| get_target_overrides(&y.metadata)));
+
+    let skyline_release = metadata
+        .packages
+        .iter()
+        .fold(None, |x, y| x.or_else(|| get_skyline_release(&y.metadata)))
+        .unwrap_or_default();
+
     Ok(Metadata {
         name,
+        version,
         title_id,
+        title_ids,
         npdm_path,
         subsdk_name,
         plugin_dependencies,
         package_resources,
+        check_imports,
+        known_exports,
+        target_dir: metadata.target_directory,
+        workspace_root: metadata.workspace_root,
+        target_json_path,
+        target_overrides,
+        linker_script_path,
+        extra_linker_script_path,
+        skyline_release,
+        toolchain,
     })
 }
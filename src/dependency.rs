@@ -0,0 +1,200 @@
+use crate::cache;
+use crate::cargo_info::Dependency;
+use crate::error::{Error, Result};
+use crate::lock::LockFile;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+/// A file from a plugin dependency along with the `rom:/` or `sd:/` path it is installed to
+pub struct DependencyFile {
+    pub install_path: String,
+    pub data: Vec<u8>,
+}
+
+/// Match a release asset name against a pattern, where `*` matches any number of characters
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    let mut rest = match name.strip_prefix(parts.next().unwrap_or("")) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        None => return rest.is_empty(),
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn get_release_asset_url(
+    owner: &str,
+    repo: &str,
+    tag: Option<&str>,
+    pattern: &str,
+    name: &str,
+) -> Result<String> {
+    let octocrab = octocrab::instance();
+    let repos = octocrab.repos(owner, repo);
+    let releases = repos.releases();
+
+    let release = match tag {
+        Some(tag) => releases.get_by_tag(tag).await?,
+        None => releases.get_latest().await?,
+    };
+
+    release
+        .assets
+        .into_iter()
+        .find(|asset| matches_pattern(pattern, &asset.name))
+        .map(|asset| asset.browser_download_url.to_string())
+        .ok_or_else(|| Error::NoMatchingAsset(name.to_owned(), pattern.to_owned()))
+}
+
+/// Whether the url recorded in Skyline.lock still applies to the dependency as given in
+/// Cargo.toml, erroring if a url dependency has changed without the lock being updated
+pub fn is_locked(dep: &Dependency, lock: &LockFile) -> Result<bool> {
+    let url = match lock.locked_url(&dep.name) {
+        Some(url) if !lock.is_updating() => url,
+        _ => return Ok(false),
+    };
+
+    match (&dep.url, &dep.tag) {
+        (Some(dep_url), _) if dep_url != url => Err(Error::LockMismatch(dep.name.clone())),
+        (Some(_), _) | (None, None) => Ok(true),
+        (None, Some(tag)) => Ok(url.contains(&format!("/releases/download/{}/", tag))),
+    }
+}
+
+/// Get the url to download a dependency from, looking up the release asset for GitHub
+/// dependencies
+fn resolve_url(dep: &Dependency, lock: Option<&LockFile>) -> Result<String> {
+    // Stick to the release recorded in Skyline.lock rather than following the latest release or
+    // looking up the same tag again, and use it when offline as the GitHub API can't be reached
+    if let Some(lock) = lock {
+        if is_locked(dep, lock)? || dep.url.is_none() && cache::is_offline() {
+            if let Some(url) = lock.locked_url(&dep.name) {
+                return Ok(url.to_owned());
+            }
+        }
+    }
+
+    if let Some(url) = &dep.url {
+        return Ok(url.clone());
+    }
+
+    let (owner, repo) = dep
+        .repo
+        .as_deref()
+        .and_then(|repo| repo.split_once('/'))
+        .ok_or_else(|| Error::InvalidDependency(dep.name.clone()))?;
+
+    if cache::is_offline() {
+        return Err(Error::NotCached(format!("https://github.com/{}/{}", owner, repo)));
+    }
+
+    let pattern = dep.asset.as_deref().unwrap_or(&dep.name);
+
+    get_release_asset_url(owner, repo, dep.tag.as_deref(), pattern, &dep.name)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Map a path within a dependency's zip to where it should be installed. Keys ending in `/`
+/// extract a whole directory.
+fn extract_path(extract: &BTreeMap<String, String>, name: &str) -> Option<String> {
+    extract.iter().find_map(|(from, to)| {
+        if from.ends_with('/') {
+            let rest = name.strip_prefix(from.as_str())?;
+
+            Some(format!("{}/{}", to.trim_end_matches('/'), rest))
+        } else if from == name && to.ends_with('/') {
+            Some(format!("{}{}", to, file_name(name)))
+        } else if from == name {
+            Some(to.clone())
+        } else {
+            None
+        }
+    })
+}
+
+fn extract(dep: &Dependency, data: Vec<u8>) -> Result<Vec<DependencyFile>> {
+    let bad_zip = |_| Error::BadDependencyZip(dep.name.clone());
+
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(bad_zip)?;
+
+    let missing = dep
+        .extract
+        .keys()
+        .filter(|from| !from.ends_with('/'))
+        .any(|from| !zip.file_names().any(|name| name == from));
+
+    if missing {
+        return Err(Error::BadDependencyZip(dep.name.clone()));
+    }
+
+    let mut files = Vec::new();
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).map_err(bad_zip)?;
+
+        if file.is_dir() {
+            continue;
+        }
+
+        // Without an extraction map release zips are assumed to mirror the root of the SD card
+        let install_path = if dep.extract.is_empty() {
+            Some(format!("sd:/{}", file.name()))
+        } else {
+            extract_path(&dep.extract, file.name())
+        };
+
+        if let Some(install_path) = install_path {
+            let mut data = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut data)?;
+
+            files.push(DependencyFile { install_path, data });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Download a plugin dependency, checking it against Skyline.lock if given, and return every file
+/// it installs
+pub fn download(dep: &Dependency, lock: Option<&mut LockFile>) -> Result<Vec<DependencyFile>> {
+    let url = resolve_url(dep, lock.as_deref())?;
+    let data = cache::download(&url)?;
+
+    if let Some(lock) = lock {
+        lock.verify_dependency(
+            &dep.name,
+            &url,
+            dep.version.as_deref(),
+            dep.sha256.as_deref(),
+            &data,
+        )?;
+    }
+
+    if dep.is_archive() {
+        extract(dep, data)
+    } else {
+        Ok(vec![DependencyFile {
+            install_path: dep.install_path(),
+            data,
+        }])
+    }
+}
@@ -25,7 +25,7 @@ pub enum Error {
     AbsSwitchPath,
     BadSdPath,
     GithubError(octocrab::Error),
-    //InvalidRepo,
+    InvalidRepo,
     //HostNotSupported,
     DownloadFailed,
     RustupNotFound,
@@ -35,8 +35,36 @@ pub enum Error {
     GitNotInstalled,
     StdCloneFailed,
     NoBaseCommit,
+    InvalidToolchainName(String),
+    ReservedToolchainName(String),
+    ToolchainNotInstalled(String),
+    RemoveDefaultToolchain(String),
+    BadToolchainArchive,
+    UnknownTemplate(String),
+    UnknownGame(String),
+    NoProjectName,
+    TemplateCloneFailed(String),
+    NeedsConfirmation(String),
+    ToolchainIncomplete(String),
+    ToolchainVerifyFailed(String, usize),
     ProjectAlreadyExists,
     FailCreateProject,
+    BadBinaryFormat,
+    UnresolvedImports(usize),
+    BadTargetJson,
+    NotCached(String),
+    BadLockFile,
+    BadNpdm,
+    BadNpdmDescription(String),
+    NpdmDescriptionMissing(PathBuf),
+    FlatLayoutMultipleTitles,
+    PackageVerifyFailed(usize),
+    InvalidDependency(String),
+    NoMatchingAsset(String, String),
+    BadDependencyZip(String),
+    LockMismatch(String),
+    ChecksumMismatch(String, String, String),
+    TargetFileMissing(PathBuf),
     PackageResourceMissing(PathBuf)
 }
 
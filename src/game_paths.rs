@@ -14,6 +14,21 @@ pub fn get_plugin_path(title_id: &str, plugin_name: &str) -> String {
     )
 }
 
+/// Resolve a `rom:/` (relative to the title's romfs) or `sd:/` install path to an absolute path
+pub fn get_sd_path(title_id: &str, path: &str) -> Option<String> {
+    if let Some(rom_path) = path.strip_prefix("rom:/") {
+        Some(format!(
+            "{}/{}/romfs/{}",
+            ATMOSPHERE_CONTENTS_DIR,
+            title_id,
+            rom_path.trim_start_matches('/')
+        ))
+    } else {
+        path.strip_prefix("sd:/")
+            .map(|sd_path| format!("/{}", sd_path.trim_start_matches('/')))
+    }
+}
+
 pub fn get_game_path(title_id: &str) -> String {
     format!("{}/{}", ATMOSPHERE_CONTENTS_DIR, title_id)
 }
@@ -0,0 +1,486 @@
+use crate::build;
+use crate::error::{Error, Result};
+use goblin::elf::dynamic::{DT_HASH, DT_NULL, DT_STRTAB, DT_SYMENT, DT_SYMTAB};
+use goblin::elf::note::NT_GNU_BUILD_ID;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::section_header::SHN_UNDEF;
+use goblin::elf::sym::{st_bind, STB_GLOBAL, STB_WEAK};
+use goblin::elf::Elf;
+use owo_colors::OwoColorize;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Names of the PT_LOAD segments, in the order they're laid out by link.T
+const SEGMENT_NAMES: &[&str] = &["text", "rodata", "data", "bss"];
+
+const HOOK_ARRAY_START: &str = "__hook_array_start";
+const HOOK_ARRAY_END: &str = "__hook_array_end";
+
+#[derive(Serialize)]
+pub struct Segment {
+    pub name: &'static str,
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct Hooks {
+    pub address: u64,
+    pub size: u64,
+    /// Only available when inspecting an ELF, as NROs/NSOs don't carry a full symbol table
+    pub entries: Vec<Symbol>,
+}
+
+#[derive(Serialize)]
+pub struct BinaryInfo {
+    pub path: PathBuf,
+    pub format: &'static str,
+    pub module_name: Option<String>,
+    pub build_id: Option<String>,
+    pub segments: Vec<Segment>,
+    pub hooks: Option<Hooks>,
+    pub exports: Vec<Symbol>,
+    pub imports: Vec<String>,
+}
+
+/// Add to an offset read from the binary, which may be anything in a malformed one
+fn add(offset: usize, rhs: usize) -> Result<usize> {
+    offset.checked_add(rhs).ok_or(Error::BadBinaryFormat)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..add(offset, 4)?)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::BadBinaryFormat)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..add(offset, 8)?)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::BadBinaryFormat)
+}
+
+fn read_cstr(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parse the `ModuleName` struct emitted into `.rodata.module_name` (a u32 of 0, followed by a
+/// u32 length and the name itself)
+fn parse_module_name(data: &[u8]) -> Option<String> {
+    if read_u32(data, 0).ok()? != 0 {
+        return None;
+    }
+
+    let len = read_u32(data, 4).ok()? as usize;
+    let name = data.get(8..8 + len)?;
+    let name = std::str::from_utf8(name).ok()?.trim_end_matches('\0');
+
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+/// Parse a dynamic symbol table, sorting each symbol into either the exports or the imports
+/// depending on whether it is defined within the module
+fn parse_dynsyms(
+    data: &[u8],
+    symtab: usize,
+    count: usize,
+    syment: usize,
+    strtab: usize,
+) -> Result<(Vec<Symbol>, Vec<String>)> {
+    let mut exports = Vec::new();
+    let mut imports = Vec::new();
+
+    for i in 0..count {
+        let sym = add(symtab, i.checked_mul(syment).ok_or(Error::BadBinaryFormat)?)?;
+        let name = read_cstr(data, add(strtab, read_u32(data, sym)? as usize)?).unwrap_or_default();
+
+        if name.is_empty() {
+            continue;
+        }
+
+        // st_info, st_other and st_shndx packed into a single little endian word
+        let packed = read_u32(data, add(sym, 4)?)?;
+
+        if (packed >> 16) == SHN_UNDEF {
+            imports.push(name);
+        } else if matches!(st_bind(packed as u8), STB_GLOBAL | STB_WEAK) {
+            exports.push(Symbol {
+                name,
+                address: read_u64(data, add(sym, 8)?)?,
+                size: read_u64(data, add(sym, 0x10)?)?,
+            });
+        }
+    }
+
+    Ok((exports, imports))
+}
+
+fn hooks_from_exports(exports: &[Symbol]) -> Option<Hooks> {
+    let find = |name| exports.iter().find(|sym| sym.name == name);
+
+    let start = find(HOOK_ARRAY_START)?.address;
+    let end = find(HOOK_ARRAY_END)?.address;
+
+    Some(Hooks {
+        address: start,
+        size: end.saturating_sub(start),
+        entries: Vec::new(),
+    })
+}
+
+fn parse_elf(path: PathBuf, data: &[u8]) -> Result<BinaryInfo> {
+    let elf = Elf::parse(data).map_err(|_| Error::BadBinaryFormat)?;
+
+    let segments = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .zip(SEGMENT_NAMES)
+        .map(|(phdr, name)| Segment {
+            name,
+            address: phdr.p_vaddr,
+            size: phdr.p_memsz,
+        })
+        .collect();
+
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .find(|shdr| elf.shdr_strtab.get_at(shdr.sh_name) == Some(name))
+    };
+
+    let module_name = section(".module_name").and_then(|shdr| {
+        let range = shdr.file_range()?;
+        parse_module_name(data.get(range)?)
+    });
+
+    let build_id = elf
+        .iter_note_sections(data, None)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .find(|note| note.n_type == NT_GNU_BUILD_ID)
+        .map(|note| to_hex(note.desc));
+
+    // link.T doesn't place .dynamic in the PT_DYNAMIC segment, so go through the section headers
+    let (exports, imports) = match (section(".dynsym"), section(".dynstr")) {
+        (Some(dynsym), Some(dynstr)) => {
+            let syment = (dynsym.sh_entsize as usize).max(1);
+
+            parse_dynsyms(
+                data,
+                dynsym.sh_offset as usize,
+                dynsym.sh_size as usize / syment,
+                syment,
+                dynstr.sh_offset as usize,
+            )?
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+
+    let hooks = match section(".hooks") {
+        Some(shdr) => {
+            let range = shdr.sh_addr..shdr.sh_addr.saturating_add(shdr.sh_size);
+
+            let mut entries: Vec<Symbol> = elf
+                .syms
+                .iter()
+                .filter(|sym| sym.st_size != 0 && range.contains(&sym.st_value))
+                .map(|sym| Symbol {
+                    name: elf.strtab.get_at(sym.st_name).unwrap_or_default().to_owned(),
+                    address: sym.st_value,
+                    size: sym.st_size,
+                })
+                .collect();
+
+            entries.sort_by_key(|sym| sym.address);
+
+            Some(Hooks {
+                address: shdr.sh_addr,
+                size: shdr.sh_size,
+                entries,
+            })
+        }
+        None => hooks_from_exports(&exports),
+    };
+
+    Ok(BinaryInfo {
+        path,
+        format: "ELF",
+        module_name,
+        build_id,
+        segments,
+        hooks,
+        exports,
+        imports,
+    })
+}
+
+/// Parse the dynamic symbol table of a loaded NRO/NSO image by following the MOD0 header
+fn parse_image_dynsyms(image: &[u8]) -> Result<(Vec<Symbol>, Vec<String>)> {
+    let mod0 = read_u32(image, 4)? as usize;
+    if image.get(mod0..add(mod0, 4)?) != Some(&b"MOD0"[..]) {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let dynamic = (mod0 as i64 + read_u32(image, add(mod0, 4)?)? as i32 as i64) as usize;
+
+    let mut symtab = None;
+    let mut strtab = None;
+    let mut hash = None;
+    let mut syment = 0x18;
+
+    for entry in (dynamic..image.len()).step_by(0x10) {
+        let tag = read_u64(image, entry)?;
+        let value = read_u64(image, entry + 8)? as usize;
+
+        match tag {
+            DT_NULL => break,
+            DT_SYMTAB => symtab = Some(value),
+            DT_STRTAB => strtab = Some(value),
+            DT_HASH => hash = Some(value),
+            DT_SYMENT => syment = value.max(1),
+            _ => (),
+        }
+    }
+
+    let (symtab, strtab) = match (symtab, strtab) {
+        (Some(symtab), Some(strtab)) => (symtab, strtab),
+        _ => return Ok((Vec::new(), Vec::new())),
+    };
+
+    // DT_HASH's nchain is the number of symbols, otherwise assume .dynstr directly follows .dynsym
+    let count = match hash {
+        Some(hash) => read_u32(image, add(hash, 4)?)? as usize,
+        None => strtab.saturating_sub(symtab) / syment,
+    };
+
+    parse_dynsyms(image, symtab, count, syment, strtab)
+}
+
+fn parse_image(
+    path: PathBuf,
+    format: &'static str,
+    image: &[u8],
+    segments: Vec<Segment>,
+    build_id: &[u8],
+) -> Result<BinaryInfo> {
+    // .module_name is the first thing placed in the rodata segment
+    let module_name = image
+        .get(segments[1].address as usize..)
+        .and_then(parse_module_name);
+
+    let build_id = build_id
+        .iter()
+        .rposition(|&b| b != 0)
+        .map(|end| to_hex(&build_id[..=end]));
+
+    let (exports, imports) = parse_image_dynsyms(image)?;
+    let hooks = hooks_from_exports(&exports);
+
+    Ok(BinaryInfo {
+        path,
+        format,
+        module_name,
+        build_id,
+        segments,
+        hooks,
+        exports,
+        imports,
+    })
+}
+
+fn parse_nro(path: PathBuf, data: &[u8]) -> Result<BinaryInfo> {
+    let mut segments = SEGMENT_NAMES[..3]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            Ok(Segment {
+                name,
+                address: read_u32(data, 0x20 + i * 8)? as u64,
+                size: read_u32(data, 0x24 + i * 8)? as u64,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let data_segment = &segments[2];
+    let bss_address = data_segment.address + data_segment.size;
+    segments.push(Segment {
+        name: SEGMENT_NAMES[3],
+        address: bss_address,
+        size: read_u32(data, 0x38)? as u64,
+    });
+
+    let build_id = data.get(0x40..0x60).ok_or(Error::BadBinaryFormat)?;
+
+    // NROs are stored uncompressed with each segment at its file offset
+    parse_image(path, "NRO", data, segments, build_id)
+}
+
+/// Far larger than the main NSO of any game, which are at most a few hundred MiB once loaded
+const MAX_NSO_IMAGE_SIZE: usize = 0x4000_0000;
+
+fn parse_nso(path: PathBuf, data: &[u8]) -> Result<BinaryInfo> {
+    let flags = read_u32(data, 0xc)?;
+
+    let mut image = Vec::new();
+    let mut segments = Vec::new();
+
+    for (i, name) in SEGMENT_NAMES[..3].iter().enumerate() {
+        let file_offset = read_u32(data, 0x10 + i * 0x10)? as usize;
+        let address = read_u32(data, 0x14 + i * 0x10)? as usize;
+        let size = read_u32(data, 0x18 + i * 0x10)? as usize;
+        let compressed_size = read_u32(data, 0x60 + i * 4)? as usize;
+
+        let raw = data
+            .get(file_offset..add(file_offset, compressed_size)?)
+            .ok_or(Error::BadBinaryFormat)?;
+
+        // Don't allocate whatever a malformed header claims the image needs
+        if add(address, size)? > MAX_NSO_IMAGE_SIZE {
+            return Err(Error::BadBinaryFormat);
+        }
+
+        let contents = if flags & (1 << i) != 0 {
+            let size = i32::try_from(size).map_err(|_| Error::BadBinaryFormat)?;
+
+            lz4::block::decompress(raw, Some(size)).map_err(|_| Error::BadBinaryFormat)?
+        } else {
+            raw.to_vec()
+        };
+
+        if image.len() < address + size {
+            image.resize(address + size, 0);
+        }
+        image[address..address + contents.len().min(size)]
+            .copy_from_slice(&contents[..contents.len().min(size)]);
+
+        segments.push(Segment {
+            name,
+            address: address as u64,
+            size: size as u64,
+        });
+    }
+
+    segments.push(Segment {
+        name: SEGMENT_NAMES[3],
+        address: image.len() as u64,
+        size: read_u32(data, 0x3c)? as u64,
+    });
+
+    let build_id = data.get(0x40..0x60).ok_or(Error::BadBinaryFormat)?;
+
+    parse_image(path, "NSO", &image, segments, build_id)
+}
+
+pub fn parse_binary(path: &Path) -> Result<BinaryInfo> {
+    parse_binary_data(path.to_owned(), &fs::read(path)?)
+}
+
+pub fn parse_binary_data(path: PathBuf, data: &[u8]) -> Result<BinaryInfo> {
+    if data.starts_with(b"\x7fELF") {
+        parse_elf(path, data)
+    } else if data.get(0x10..0x14) == Some(&b"NRO0"[..]) {
+        parse_nro(path, data)
+    } else if data.starts_with(b"NSO0") {
+        parse_nso(path, data)
+    } else {
+        Err(Error::BadBinaryFormat)
+    }
+}
+
+fn print_symbol(sym: &Symbol) {
+    println!(
+        "  {:#010x} {:>8x}  {:#}",
+        sym.address,
+        sym.size,
+        rustc_demangle::demangle(&sym.name)
+    );
+}
+
+fn print_info(info: &BinaryInfo) {
+    println!("{}: {}", "Path".bright_blue(), info.path.display());
+    println!("{}: {}", "Format".bright_blue(), info.format);
+    println!(
+        "{}: {}",
+        "Module name".bright_blue(),
+        info.module_name.as_deref().unwrap_or("<none>")
+    );
+    println!(
+        "{}: {}",
+        "Build ID".bright_blue(),
+        info.build_id.as_deref().unwrap_or("<none>")
+    );
+
+    println!("\n{}:", "Segments".bright_blue());
+    for segment in &info.segments {
+        println!(
+            "  {:<8} {:#010x} {:>8x}",
+            segment.name, segment.address, segment.size
+        );
+    }
+
+    match &info.hooks {
+        Some(hooks) => {
+            println!(
+                "\n{}: {:#x} bytes at {:#010x}",
+                "Hooks".bright_blue(),
+                hooks.size,
+                hooks.address
+            );
+            hooks.entries.iter().for_each(print_symbol);
+        }
+        None => println!("\n{}: <none>", "Hooks".bright_blue()),
+    }
+
+    println!("\n{} ({}):", "Exports".bright_blue(), info.exports.len());
+    info.exports.iter().for_each(print_symbol);
+
+    println!("\n{} ({}):", "Imports".bright_blue(), info.imports.len());
+    for import in &info.imports {
+        println!("  {:#}", rustc_demangle::demangle(import));
+    }
+}
+
+pub fn inspect(
+    path: Option<String>,
+    release: bool,
+    json: bool,
+    mut args: Vec<String>,
+) -> Result<()> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            if release {
+                args.push("--release".into());
+            }
+
+            build::build_get_artifact(args)?
+        }
+    };
+
+    let info = parse_binary(&path)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info).unwrap());
+    } else {
+        print_info(&info);
+    }
+
+    Ok(())
+}
@@ -1,10 +1,15 @@
 use crate::error::{Error, Result};
 use crate::ftp::FtpClient;
-use crate::game_paths::{get_game_path, get_plugin_path, get_plugins_path};
+use crate::game_paths::{get_game_path, get_plugin_path, get_plugins_path, get_sd_path};
 use crate::ip_addr::{get_ip, verify_ip};
+use crate::lock::LockFile;
 use crate::tcp_listen;
-use crate::{build, cargo_info};
+use crate::update_std::PathExt;
+use crate::{build, cache, cargo_info, dependency};
 use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use temp_git::TempGitDir;
@@ -39,13 +44,139 @@ fn warn_if_old_skyline_subsdk(client: &mut FtpClient, exefs_path: &str) {
     }
 }
 
+/// Create every directory leading up to the given file, ignoring those which already exist
+fn ensure_parent_dirs(client: &mut FtpClient, path: &str) {
+    let parent = path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+
+    let mut dir = String::new();
+    for part in parent.split('/').filter(|x| !x.is_empty()) {
+        dir = format!("{}/{}", dir, part);
+        let _ = client.mkdir(&dir);
+    }
+}
+
+/// A download last installed to a console, recorded so that it only needs downloading again when
+/// it no longer matches Skyline.lock
+#[derive(Serialize, Deserialize)]
+struct InstalledDownload {
+    sha256: String,
+    paths: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct InstalledTitle {
+    skyline: Option<InstalledDownload>,
+    #[serde(default)]
+    dependencies: BTreeMap<String, InstalledDownload>,
+}
+
+/// Skyline and plugin dependencies last installed to each console, keyed by IP then title ID
+type InstalledDownloads = BTreeMap<String, BTreeMap<String, InstalledTitle>>;
+
+fn installed_downloads_path(metadata: &cargo_info::Metadata) -> PathBuf {
+    metadata
+        .target_dir
+        .clone()
+        .push_join("skyline")
+        .ensure_exists()
+        .push_join("installed-downloads.json")
+}
+
+/// Whether what was last installed is still what's locked (and pinned, if given) and every file
+/// it installed is still on the console
+fn is_up_to_date(
+    client: &mut FtpClient,
+    installed: Option<&InstalledDownload>,
+    locked_sha256: Option<&str>,
+    pinned_sha256: Option<&str>,
+) -> bool {
+    let installed = match installed {
+        Some(installed) => installed,
+        None => return false,
+    };
+
+    locked_sha256 == Some(installed.sha256.as_str())
+        && pinned_sha256.is_none_or(|pinned| pinned.eq_ignore_ascii_case(&installed.sha256))
+        && installed
+            .paths
+            .iter()
+            .all(|path| client.file_exists(path).unwrap_or(false))
+}
+
+/// Hashes of the package resources last uploaded to each console, keyed by IP then SD path
+type UploadedResources = BTreeMap<String, BTreeMap<String, String>>;
+
+fn uploaded_resources_path(metadata: &cargo_info::Metadata) -> PathBuf {
+    metadata
+        .target_dir
+        .clone()
+        .push_join("skyline")
+        .ensure_exists()
+        .push_join("uploaded-resources.json")
+}
+
+/// Upload `package-resources` to the same place on the SD card that `package` puts them in the
+/// zip, skipping files which haven't changed since they were last uploaded to this console
+fn install_resources(
+    client: &mut FtpClient,
+    ip: IpAddr,
+    metadata: &cargo_info::Metadata,
+) -> Result<()> {
+    let files = crate::package::resource_files(&metadata.package_resources);
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let record_path = uploaded_resources_path(metadata);
+    let mut record: UploadedResources = fs::read(&record_path)
+        .ok()
+        .and_then(|record| serde_json::from_slice(&record).ok())
+        .unwrap_or_default();
+    let ip = ip.to_string();
+
+    println!("Installing package resources...");
+
+    let mut unchanged = 0;
+    for (local_path, package_path) in files {
+        let data = fs::read(&local_path).map_err(|_| Error::PackageResourceMissing(local_path))?;
+        let sha256 = cache::sha256_hex(&data);
+        let sd_path = format!(
+            "/{}",
+            package_path.to_str().unwrap().trim_start_matches('/')
+        );
+
+        let previous = record.get(&ip).and_then(|uploaded| uploaded.get(&sd_path));
+        let is_unchanged = previous == Some(&sha256)
+            && client.file_exists(&sd_path).unwrap_or(false);
+
+        if is_unchanged {
+            unchanged += 1;
+            continue;
+        }
+
+        println!("   └ {}", sd_path);
+        ensure_parent_dirs(client, &sd_path);
+        client.put(&sd_path, data)?;
+        record.entry(ip.clone()).or_default().insert(sd_path, sha256);
+
+        // Keep the record up to date in case a later upload fails
+        fs::write(&record_path, serde_json::to_string_pretty(&record).unwrap())?;
+    }
+
+    if unchanged > 0 {
+        println!("{} unchanged resource(s) skipped", unchanged);
+    }
+
+    Ok(())
+}
+
 fn parse_tid(tid: &str) -> u64 {
     u64::from_str_radix(tid, 16).expect("Invalid Title ID")
 }
 
-static SKYLINE_URL: &str =
+pub(crate) static SKYLINE_URL: &str =
     "https://github.com/skyline-dev/skyline/releases/download/beta/skyline.zip";
-static TEMPLATE_NPDM: &[u8] = include_bytes!("template.npdm");
+pub(crate) static TEMPLATE_NPDM: &[u8] = include_bytes!("template.npdm");
 
 pub fn generate_npdm(tid: &str) -> Vec<u8> {
     [
@@ -56,6 +187,7 @@ pub fn generate_npdm(tid: &str) -> Vec<u8> {
     .concat()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn install(
     ip: Option<String>,
     title_id: Option<String>,
@@ -63,12 +195,12 @@ pub fn install(
     features: Vec<String>,
     path: Option<String>,
     no_default_features: bool,
+    update_lock: bool,
+    mut args: Vec<String>,
 ) -> Result<()> {
-    let mut args = if release {
-        vec![String::from("--release")]
-    } else {
-        vec![]
-    };
+    if release {
+        args.push(String::from("--release"));
+    }
 
     if !features.is_empty() {
         args.push(format!("--features={}", features.join(",")));
@@ -102,10 +234,12 @@ pub fn install(
         .or_else(|| metadata.title_id.clone())
         .ok_or(Error::NoTitleId)?;
 
+    let mut lock = LockFile::load(&metadata.workspace_root, update_lock)?;
+
     println!("Ensuring directory exists...");
 
     // this is where subsdk9 goes, it doesn't depend on the path
-    let _ = client.mkdir(&get_game_path(&title_id));
+    let _ = client.mkdir(get_game_path(&title_id));
     let _ = client.mkdir(&(get_game_path(&title_id) + "/exefs"));
 
     let dirs = path
@@ -128,36 +262,121 @@ pub fn install(
 
     warn_if_old_skyline_subsdk(&mut client, &(get_game_path(&title_id) + "/exefs/"));
 
-    // Ensure skyline is installed if it doesn't exist
+    // Skyline and plugin dependencies are checked against Skyline.lock on every install, so that
+    // anything on the console which doesn't match gets replaced. What was installed is recorded
+    // rather than read back from the console, so nothing is downloaded while it still matches.
+    let record_path = installed_downloads_path(&metadata);
+    let mut record: InstalledDownloads = fs::read(&record_path)
+        .ok()
+        .and_then(|record| serde_json::from_slice(&record).ok())
+        .unwrap_or_default();
+    let ip_key = ip.to_string();
+
+    let release = &metadata.skyline_release;
+    let url = lock.skyline_url(&release.url())?;
     let subsdk_path = get_game_path(&title_id) + "/exefs/subsdk9";
-    if !client.file_exists(&subsdk_path).expect("Failed to check if subsdk9 exists") {
-        println!("Skyline subsdk not installed for the given title, downloading...");
-        let exefs = crate::package::get_exefs(SKYLINE_URL)?;
-        println!("Installing over subsdk9...");
-        client.put(&subsdk_path, exefs.subsdk1)?;
+
+    let skyline_up_to_date = !lock.is_updating()
+        && is_up_to_date(
+            &mut client,
+            record
+                .get(&ip_key)
+                .and_then(|titles| titles.get(&title_id))
+                .and_then(|title| title.skyline.as_ref()),
+            lock.skyline_sha256(),
+            release.sha256.as_deref(),
+        );
+
+    if !skyline_up_to_date {
+        match cache::download(&url) {
+            // Offline without a cached copy, the Skyline already on the console is the best there is
+            Err(Error::NotCached(_)) if client.file_exists(&subsdk_path).unwrap_or(false) => {
+                eprintln!(
+                    "{}: Skyline isn't cached, so the installed subsdk9 can't be checked against Skyline.lock",
+                    "WARN".yellow().bold()
+                );
+            }
+            data => {
+                let data = data?;
+                lock.verify_skyline(
+                    &url,
+                    release.version.as_deref(),
+                    release.sha256.as_deref(),
+                    &data,
+                )?;
+                let sha256 = cache::sha256_hex(&data);
+                let subsdk = crate::package::exefs_from_zip(data)?.subsdk1;
+
+                println!("Skyline subsdk not installed or outdated for the given title, installing over subsdk9...");
+                client.put(&subsdk_path, subsdk)?;
+
+                let installed = InstalledDownload {
+                    sha256,
+                    paths: vec![subsdk_path.clone()],
+                };
+                record
+                    .entry(ip_key.clone())
+                    .or_default()
+                    .entry(title_id.clone())
+                    .or_default()
+                    .skyline = Some(installed);
+                fs::write(&record_path, serde_json::to_string_pretty(&record).unwrap())?;
+            }
+        }
     }
 
     let npdm_path = get_game_path(&title_id) + "/exefs/main.npdm";
     if !client.file_exists(&npdm_path).expect("Failed to check if NPDM exists") {
         println!("Skyline npdm not installed for the given title, generating and installing...");
-        client.put(&npdm_path, generate_npdm(&title_id))?;
+        let npdm = crate::npdm::custom_npdm(&metadata, &title_id)?
+            .unwrap_or_else(|| generate_npdm(&title_id));
+        client.put(&npdm_path, npdm)?;
     }
 
     for dep in &metadata.plugin_dependencies {
-        let dep_path = get_plugin_path(&title_id, &dep.name);
-        if !client.file_exists(&dep_path).unwrap_or(false) {
-            println!("Downloading dependency {}...", dep.name);
-            let dep_data = reqwest::blocking::get(&dep.url)
-                .map_err(|_| Error::DownloadError)?
-                .bytes()
-                .map_err(|_| Error::DownloadError)?;
-            println!("Installing dependency {}...", dep.name);
-            client.put(dep_path, &dep_data).unwrap();
+        let up_to_date = dependency::is_locked(dep, &lock)?
+            && is_up_to_date(
+                &mut client,
+                record
+                    .get(&ip_key)
+                    .and_then(|titles| titles.get(&title_id))
+                    .and_then(|title| title.dependencies.get(&dep.name)),
+                lock.dependency_sha256(&dep.name),
+                dep.sha256.as_deref(),
+            );
+
+        if up_to_date {
+            continue;
         }
+
+        let files = dependency::download(dep, Some(&mut lock))?;
+
+        println!("Installing dependency {}...", dep.name);
+        let mut paths = Vec::new();
+        for file in files {
+            let path = get_sd_path(&title_id, &file.install_path).ok_or(Error::BadSdPath)?;
+
+            ensure_parent_dirs(&mut client, &path);
+            client.put(&path, file.data)?;
+            paths.push(path);
+        }
+
+        let sha256 = lock
+            .dependency_sha256(&dep.name)
+            .unwrap_or_default()
+            .to_owned();
+        record
+            .entry(ip_key.clone())
+            .or_default()
+            .entry(title_id.clone())
+            .or_default()
+            .dependencies
+            .insert(dep.name.clone(), InstalledDownload { sha256, paths });
+        fs::write(&record_path, serde_json::to_string_pretty(&record).unwrap())?;
     }
 
     let nro_name = if path.ends_with(".nro") {
-        path.split('/').last().unwrap()
+        path.split('/').next_back().unwrap()
     } else {
         nro_path
             .file_name()
@@ -165,6 +384,10 @@ pub fn install(
             .ok_or(Error::FailWriteNro)?
     };
 
+    lock.save()?;
+
+    install_resources(&mut client, ip, &metadata)?;
+
     println!("Transferring file...");
     client.put(
         format!("{}/{}", plugin_folder_path, nro_name),
@@ -174,6 +397,7 @@ pub fn install(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn from_git(
     git: &str,
     ip: Option<String>,
@@ -182,10 +406,21 @@ pub fn from_git(
     features: Vec<String>,
     path: Option<String>,
     no_default_features: bool,
+    update_lock: bool,
+    args: Vec<String>,
 ) -> Result<()> {
     let temp_dir = TempGitDir::clone_to_current_dir(git)?;
 
-    install(ip, title_id, release, features, path, no_default_features)?;
+    install(
+        ip,
+        title_id,
+        release,
+        features,
+        path,
+        no_default_features,
+        update_lock,
+        args,
+    )?;
 
     temp_dir.delete();
 
@@ -217,6 +452,7 @@ pub fn restart_game(ip: Option<String>, title_id: Option<String>) -> Result<()>
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn install_and_run(
     ip: Option<String>,
     title_id: Option<String>,
@@ -225,6 +461,8 @@ pub fn install_and_run(
     features: Vec<String>,
     path: Option<String>,
     no_default_features: bool,
+    update_lock: bool,
+    args: Vec<String>,
 ) -> Result<()> {
     install(
         ip.clone(),
@@ -233,6 +471,8 @@ pub fn install_and_run(
         features,
         path,
         no_default_features,
+        update_lock,
+        args,
     )?;
 
     if restart {
@@ -253,8 +493,8 @@ pub fn list(ip: Option<String>, title_id: Option<String>, path: Option<String>)
 
     let mut client = connect(ip, false)?;
 
-    if path.is_some() {
-        println!("{}", client.ls(Some(&path.unwrap()))?);
+    if let Some(path) = path {
+        println!("{}", client.ls(Some(&path))?);
         return Ok(());
     }
 
@@ -272,8 +512,7 @@ pub fn list(ip: Option<String>, title_id: Option<String>, path: Option<String>)
  ** 3. Filename isn't populated. Install path is current plugin NRO's default install path.
 */
 fn get_install_path(title_id: Option<String>, filename: Option<String>) -> Result<String> {
-    if filename.is_some() {
-        let filename_str = (&filename).as_ref().unwrap();
+    if let Some(filename_str) = &filename {
         if filename_str.starts_with('/') {
             return Ok(filename_str.to_string());
         }
@@ -13,7 +13,7 @@ pub struct TempGitDir {
 impl TempGitDir {
     pub fn clone_to_current_dir(url: &str) -> Result<Self> {
         Command::new("git")
-            .args(&["clone", url, "tempdir_j93jfs3ff"])
+            .args(["clone", url, "tempdir_j93jfs3ff"])
             .status()?;
         let previous_dir = env::current_dir()?;
         env::set_current_dir(previous_dir.join("tempdir_j93jfs3ff"))?;
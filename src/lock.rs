@@ -0,0 +1,191 @@
+use crate::cache::sha256_hex;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = "Skyline.lock";
+const SKYLINE_NAME: &str = "skyline";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LockEntry {
+    pub name: String,
+    pub url: String,
+    pub version: Option<String>,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct LockData {
+    skyline: Option<LockEntry>,
+    #[serde(default, rename = "dependency")]
+    dependencies: Vec<LockEntry>,
+}
+
+/// `Skyline.lock`, recording the resolved url and hash of Skyline and every plugin dependency so
+/// that everyone working on a plugin installs and packages the same binaries
+pub struct LockFile {
+    path: PathBuf,
+    data: LockData,
+    update: bool,
+    dirty: bool,
+}
+
+impl LockFile {
+    /// Load the lock file from the root of the given workspace. With `update` set, entries which
+    /// no longer match are replaced rather than treated as an error.
+    pub fn load(workspace_root: &Path, update: bool) -> Result<Self> {
+        let path = workspace_root.join(LOCK_FILE);
+
+        let data = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|_| Error::BadLockFile)?,
+            Err(_) => LockData::default(),
+        };
+
+        Ok(Self {
+            path,
+            data,
+            update,
+            dirty: false,
+        })
+    }
+
+    pub fn is_updating(&self) -> bool {
+        self.update
+    }
+
+    /// The url a dependency was last resolved to
+    pub fn locked_url(&self, name: &str) -> Option<&str> {
+        self.data
+            .dependencies
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.url.as_str())
+    }
+
+    /// The hash Skyline was last locked to
+    pub fn skyline_sha256(&self) -> Option<&str> {
+        self.data.skyline.as_ref().map(|entry| entry.sha256.as_str())
+    }
+
+    /// The hash a dependency was last locked to
+    pub fn dependency_sha256(&self, name: &str) -> Option<&str> {
+        self.data
+            .dependencies
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| entry.sha256.as_str())
+    }
+
+    /// The url to download Skyline from given the one in Cargo.toml, which has to be the one
+    /// recorded in the lock file unless it's being updated
+    pub fn skyline_url(&self, url: &str) -> Result<String> {
+        match &self.data.skyline {
+            Some(entry) if !self.update && entry.url != url => {
+                Err(Error::LockMismatch(SKYLINE_NAME.to_owned()))
+            }
+            _ => Ok(url.to_owned()),
+        }
+    }
+
+    /// Returns whether the lock file needs to be written back to disk
+    fn verify(
+        entry: Option<&mut LockEntry>,
+        new_entry: LockEntry,
+        pinned_sha256: Option<&str>,
+        update: bool,
+    ) -> Result<bool> {
+        if let Some(pinned) = pinned_sha256 {
+            if !pinned.eq_ignore_ascii_case(&new_entry.sha256) {
+                return Err(Error::ChecksumMismatch(
+                    new_entry.name,
+                    pinned.to_owned(),
+                    new_entry.sha256,
+                ));
+            }
+        }
+
+        match entry {
+            Some(entry) if *entry == new_entry => Ok(false),
+            Some(entry) if update => {
+                *entry = new_entry;
+                Ok(true)
+            }
+            Some(_) => Err(Error::LockMismatch(new_entry.name)),
+            None => Ok(true),
+        }
+    }
+
+    /// Check a downloaded Skyline release against the pinned hash and the lock file
+    pub fn verify_skyline(
+        &mut self,
+        url: &str,
+        version: Option<&str>,
+        pinned_sha256: Option<&str>,
+        data: &[u8],
+    ) -> Result<()> {
+        let new_entry = LockEntry {
+            name: SKYLINE_NAME.to_owned(),
+            url: url.to_owned(),
+            version: version.map(String::from),
+            sha256: sha256_hex(data),
+        };
+
+        let is_new = self.data.skyline.is_none();
+        let entry = self.data.skyline.as_mut();
+
+        if Self::verify(entry, new_entry.clone(), pinned_sha256, self.update)? {
+            if is_new {
+                self.data.skyline = Some(new_entry);
+            }
+            self.dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Check a downloaded plugin dependency against the pinned hash and the lock file
+    pub fn verify_dependency(
+        &mut self,
+        name: &str,
+        url: &str,
+        version: Option<&str>,
+        pinned_sha256: Option<&str>,
+        data: &[u8],
+    ) -> Result<()> {
+        let new_entry = LockEntry {
+            name: name.to_owned(),
+            url: url.to_owned(),
+            version: version.map(String::from),
+            sha256: sha256_hex(data),
+        };
+
+        let entry = self
+            .data
+            .dependencies
+            .iter_mut()
+            .find(|entry| entry.name == name);
+        let is_new = entry.is_none();
+
+        if Self::verify(entry, new_entry.clone(), pinned_sha256, self.update)? {
+            if is_new {
+                self.data.dependencies.push(new_entry);
+            }
+            self.dirty = true;
+        }
+
+        Ok(())
+    }
+
+    /// Write the lock file back to disk if anything was added or updated
+    pub fn save(&self) -> Result<()> {
+        if self.dirty {
+            fs::write(
+                &self.path,
+                toml::to_string_pretty(&self.data).map_err(|_| Error::BadLockFile)?,
+            )?;
+        }
+
+        Ok(())
+    }
+}
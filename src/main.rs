@@ -6,25 +6,65 @@ use std::fs;
 use std::process::Command;
 
 mod build;
+mod cache;
 mod cargo_info;
 mod error;
+mod dependency;
 mod ftp;
 mod game_paths;
+mod inspect;
 mod installer;
 mod ip_addr;
+mod lock;
+mod npdm;
 mod new_plugin;
 mod package;
+mod prompt;
+mod size;
 mod tcp_listen;
+mod toolchain;
 mod update_std;
 
 #[derive(StructOpt)]
 enum SubCommands {
     #[structopt(about = "Create a new plugin from a template")]
-    New { name: String },
+    New {
+        #[structopt(about = "Name of the plugin, asking for everything interactively if left out")]
+        name: Option<String>,
+
+        #[structopt(
+            short,
+            long,
+            about = "Template to create the plugin from: hello (the default), inline-hook, symbol-hook, subsdk, library, workspace, or a local directory or git url"
+        )]
+        template: Option<String>,
+
+        #[structopt(
+            short,
+            long,
+            about = "Game the plugin is for, either a title ID or a known game such as smash-ultimate, botw, totk, odyssey or mk8dx"
+        )]
+        game: Option<String>,
+
+        #[structopt(long, about = "Set up logging with the `log` crate")]
+        logging: bool,
+
+        #[structopt(long, about = "Add restart-plugin as a plugin dependency")]
+        restart_plugin: bool,
+
+        #[structopt(long, about = "Bundle a resources folder when packaging")]
+        package_resources: bool,
+    },
     #[structopt(about = "Check if the current plugin builds and emit any errors found")]
     Check {
         #[structopt(long)]
         json: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
     },
     #[structopt(about = "Emit beginner-helpful lints and warnings")]
     Clippy {
@@ -46,6 +86,9 @@ enum SubCommands {
         #[structopt(long)]
         json: bool,
 
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
         #[structopt(last = true)]
         opts: Vec<String>,
     },
@@ -63,6 +106,39 @@ enum SubCommands {
         #[structopt(long)]
         features: Vec<String>,
 
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        args: Vec<String>,
+    },
+    #[structopt(about = "Show the segments, hooks and dynamic symbols of a built plugin")]
+    Inspect {
+        #[structopt(long)]
+        release: bool,
+
+        #[structopt(long)]
+        json: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(about = "ELF, NRO or NSO to inspect, defaults to building the current plugin")]
+        path: Option<String>,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    #[structopt(about = "Build the current plugin and report what is taking up space in it")]
+    Size {
+        #[structopt(long)]
+        release: bool,
+
+        #[structopt(long, default_value = "20", about = "Number of crates and functions to list")]
+        top: usize,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
         args: Vec<String>,
     },
     #[structopt(about = "Build the current plugin and install to a switch over FTP")]
@@ -91,6 +167,15 @@ enum SubCommands {
 
         #[structopt(long)]
         install_path: Option<String>,
+
+        #[structopt(long, about = "Update Skyline.lock rather than failing when a download doesn't match it")]
+        update_lock: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
     },
     #[structopt(about = "Set the IP address of the switch to install to")]
     SetIp { ip: String },
@@ -122,6 +207,15 @@ enum SubCommands {
 
         #[structopt(long)]
         install_path: Option<String>,
+
+        #[structopt(long, about = "Update Skyline.lock rather than failing when a download doesn't match it")]
+        update_lock: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
     },
     #[structopt(about = "Install the current plugin and listen for skyline logging")]
     Restart {
@@ -137,11 +231,14 @@ enum SubCommands {
     },
     #[structopt(about = "Download the latest stdlib for aarch64-skyline-switch")]
     UpdateStd {
-        #[structopt(short, long, default_value = "skyline-rs/rust")]
-        repo: String,
+        #[structopt(flatten)]
+        source: update_std::SourceArgs,
 
-        #[structopt(short, long)]
-        tag: Option<String>,
+        #[structopt(
+            long,
+            about = "Name of the toolchain to install to, defaults to the default toolchain"
+        )]
+        toolchain: Option<String>,
 
         #[structopt(
             long,
@@ -152,7 +249,7 @@ enum SubCommands {
         #[structopt(
             long,
             about = "Rather than re-clone, pull new commits. Assumes non-shallow clone.",
-            conflicts_with = "deep"
+            conflicts_with_all = &["deep", "repo", "tag", "branch", "rev", "mirror", "base-nightly", "from-archive"]
         )]
         pull: bool,
     },
@@ -223,12 +320,15 @@ enum SubCommands {
         about = "Package plugin and latest Skyline into a zip file to prepare it for release"
     )]
     Package {
+        #[structopt(subcommand)]
+        command: Option<package::PackageCommand>,
+
         #[structopt(
             short,
             long,
-            default_value = "https://github.com/skyline-dev/skyline/releases/download/beta/skyline.zip"
+            about = "Url of the Skyline release to package, defaults to `skyline-release` in Cargo.toml or the latest beta"
         )]
-        skyline_release: String,
+        skyline_release: Option<String>,
 
         #[structopt(
             short,
@@ -237,25 +337,73 @@ enum SubCommands {
         )]
         no_skyline: bool,
 
-        #[structopt(short, long, about = "Title ID of the game to package the plugin for")]
-        title_id: Option<String>,
+        #[structopt(
+            short,
+            long,
+            number_of_values = 1,
+            about = "Title ID of the game to package the plugin for, can be repeated to package for several"
+        )]
+        title_id: Vec<String>,
+
+        #[structopt(long, about = "Build a separate zip for each title ID")]
+        split: bool,
+
+        #[structopt(
+            long,
+            default_value = "atmosphere",
+            possible_values = &["atmosphere", "emulator", "flat"],
+            about = "Layout of the package: atmosphere for the SD card, emulator for Ryujinx/yuzu mod folders, or flat for mod managers"
+        )]
+        layout: package::Layout,
+
+        #[structopt(long, about = "Output a directory rather than a zip")]
+        directory: bool,
 
         #[structopt(
             short,
             long,
-            about = "Path to output zip to",
-            default_value = "target/release.zip"
+            about = "Path to output zip to, defaults to target/<profile>.zip with any features appended"
         )]
-        out_path: String,
+        out_path: Option<String>,
 
         #[structopt(
             long,
             about = "Build the project as a subsdk"
         )]
         subsdk: bool,
+
+        #[structopt(short, long, about = "Package a debug build rather than a release build")]
+        debug: bool,
+
+        #[structopt(long)]
+        no_default_features: bool,
+
+        #[structopt(long)]
+        features: Vec<String>,
+
+        #[structopt(long, about = "Update Skyline.lock rather than failing when a download doesn't match it")]
+        update_lock: bool,
+
+        #[structopt(
+            long,
+            about = "Produce a byte-for-byte identical zip from identical builds"
+        )]
+        reproducible: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
     },
+    #[structopt(about = "Generate an NPDM from a description or dump an existing one")]
+    Npdm(npdm::NpdmCommand),
     #[structopt(about = "Update libraries for current plugin folder")]
     Update,
+    #[structopt(about = "Manage downloads of Skyline and plugin dependencies cached to disk")]
+    Cache(cache::CacheCommand),
+    #[structopt(about = "Manage the installed skyline toolchains")]
+    Toolchain(toolchain::ToolchainCommand),
     #[structopt(about = "Document the current plugin and its dependencies")]
     Doc {
         #[structopt(
@@ -264,6 +412,12 @@ enum SubCommands {
             about = "Whether or not to open the docs in the default browser afterwards"
         )]
         open: bool,
+
+        #[structopt(flatten)]
+        cargo_opts: build::CargoOpts,
+
+        #[structopt(last = true)]
+        args: Vec<String>,
     },
 
     #[structopt(
@@ -275,14 +429,68 @@ enum SubCommands {
     RestartGame,
 }
 
+impl SubCommands {
+    /// The cargo options of subcommands which invoke cargo
+    fn cargo_opts(&self) -> Option<&build::CargoOpts> {
+        use SubCommands::*;
+
+        match self {
+            Install { cargo_opts, .. }
+            | Build { cargo_opts, .. }
+            | Check { cargo_opts, .. }
+            | Inspect { cargo_opts, .. }
+            | Size { cargo_opts, .. }
+            | Clippy { cargo_opts, .. }
+            | Run { cargo_opts, .. }
+            | Package { cargo_opts, .. }
+            | Doc { cargo_opts, .. } => Some(cargo_opts),
+            _ => None,
+        }
+    }
+}
+
+#[derive(StructOpt)]
+struct Skyline {
+    #[structopt(
+        short,
+        long,
+        about = "Accept any prompts, such as installing a missing toolchain"
+    )]
+    yes: bool,
+
+    #[structopt(
+        long,
+        about = "Fail rather than prompting, the default on CI or with CARGO_SKYLINE_NONINTERACTIVE set"
+    )]
+    non_interactive: bool,
+
+    #[structopt(subcommand)]
+    command: SubCommands,
+}
+
 #[derive(StructOpt)]
 #[structopt(bin_name = "cargo")]
 enum Args {
-    Skyline(SubCommands),
+    Skyline(Skyline),
 }
 
 fn main() {
-    let Args::Skyline(subcommand) = Args::from_args();
+    let Args::Skyline(args) = Args::from_args();
+
+    if args.yes {
+        prompt::set_assume_yes();
+    }
+
+    if args.non_interactive {
+        prompt::set_non_interactive();
+    }
+
+    let subcommand = args.command;
+
+    // Downloads made by cargo-skyline itself should also only come from the cache
+    if subcommand.cargo_opts().is_some_and(|opts| opts.offline) {
+        cache::set_offline();
+    }
 
     use SubCommands::*;
 
@@ -313,25 +521,35 @@ fn main() {
             features,
             no_default_features,
             install_path,
+            update_lock,
+            cargo_opts,
+            args,
         } => {
+            let release = !debug && cargo_opts.profile.is_none();
+            let args = cargo_opts.into_args(args);
+
             if let Some(git) = git {
                 installer::from_git(
                     &git,
                     ip,
                     title_id,
-                    !debug,
+                    release,
                     features,
                     install_path,
                     no_default_features,
+                    update_lock,
+                    args,
                 )
             } else {
                 installer::install(
                     ip,
                     title_id,
-                    !debug,
+                    release,
                     features,
                     install_path,
                     no_default_features,
+                    update_lock,
+                    args,
                 )
             }
         }
@@ -343,8 +561,32 @@ fn main() {
             nso,
             features,
             no_default_features,
-        } => build::build(args, release, nso, features, no_default_features),
-        Check { json } => build::check(json),
+            cargo_opts,
+        } => build::build(
+            cargo_opts.into_args(args),
+            release,
+            nso,
+            features,
+            no_default_features,
+        ),
+        Check {
+            json,
+            cargo_opts,
+            args,
+        } => build::check(cargo_opts.into_args(args), json),
+        Inspect {
+            release,
+            json,
+            cargo_opts,
+            path,
+            args,
+        } => inspect::inspect(path, release, json, cargo_opts.into_args(args)),
+        Size {
+            release,
+            top,
+            cargo_opts,
+            args,
+        } => size::size(cargo_opts.into_args(args), release, top),
         Clippy {
             no_deps,
             fix,
@@ -352,9 +594,10 @@ fn main() {
             all_features,
             no_default_features,
             json,
+            cargo_opts,
             opts,
         } => {
-            let mut args = Vec::new();
+            let mut args = cargo_opts.into_args(Vec::new());
 
             if no_deps {
                 args.push("--no-deps".into());
@@ -389,23 +632,45 @@ fn main() {
             features,
             install_path,
             no_default_features,
+            update_lock,
+            cargo_opts,
+            args,
         } => installer::install_and_run(
             ip,
             title_id,
-            !debug,
+            !debug && cargo_opts.profile.is_none(),
             restart,
             features,
             install_path,
             no_default_features,
+            update_lock,
+            cargo_opts.into_args(args),
         ),
         Restart { ip, title_id } => installer::restart_game(ip, title_id),
-        New { name } => new_plugin::new_plugin(name),
+        New {
+            name,
+            template,
+            game,
+            logging,
+            restart_plugin,
+            package_resources,
+        } => new_plugin::new_plugin(
+            name,
+            template,
+            game,
+            new_plugin::Extras {
+                logging,
+                restart_plugin,
+                package_resources,
+            },
+        ),
         UpdateStd {
-            repo,
-            tag,
+            source,
+            toolchain,
             deep,
             pull,
-        } => update_std::update_std(&repo, tag.as_deref(), deep, pull),
+        } => update_std::update_std(source, toolchain, deep, pull),
+        Toolchain(command) => toolchain::toolchain_command(command),
         Listen { ip } => tcp_listen::listen(ip),
         List { ip, title_id, path } => installer::list(ip, title_id, path),
         Rm {
@@ -421,20 +686,68 @@ fn main() {
         } => installer::cp(ip, title_id, src, dest),
         SelfUpdate { from_master, git } => self_update(from_master, git),
         Package {
+            command: Some(command),
+            ..
+        } => package::package_command(command),
+        Package {
+            command: None,
             skyline_release,
             title_id,
+            split,
+            layout,
+            directory,
             out_path,
             no_skyline,
             subsdk,
-        } => package::package(
-            &skyline_release,
-            title_id.as_deref(),
-            &out_path,
-            !no_skyline,
-            subsdk,
-        ),
+            debug,
+            no_default_features,
+            features,
+            update_lock,
+            reproducible,
+            cargo_opts,
+            args,
+        } => {
+            let release = !debug && cargo_opts.profile.is_none();
+            let profile = match &cargo_opts.profile {
+                Some(profile) => profile.clone(),
+                None if debug => String::from("debug"),
+                None => String::from("release"),
+            };
+
+            package::package(
+                skyline_release.as_deref(),
+                title_id,
+                out_path,
+                !no_skyline,
+                subsdk,
+                release,
+                &profile,
+                features,
+                no_default_features,
+                update_lock,
+                reproducible,
+                split,
+                layout,
+                directory,
+                cargo_opts.into_args(args),
+            )
+        }
+        Npdm(command) => npdm::npdm(command),
         Update => update(),
-        Doc { open } => build::doc(if open { vec!["--open".into()] } else { vec![] }),
+        Cache(command) => cache::cache(command),
+        Doc {
+            open,
+            cargo_opts,
+            args,
+        } => {
+            let mut args = cargo_opts.into_args(args);
+
+            if open {
+                args.push("--open".into());
+            }
+
+            build::doc(args)
+        }
         CleanProject => clean_project(),
         RestartGame => installer::restart_game(None, None),
     };
@@ -467,7 +780,7 @@ fn main() {
             Error::AbsSwitchPath => eprintln!("{}: Absolute Switch paths must be prepended with \"sd:/\"", error),
             Error::BadSdPath => eprintln!("{}: Install paths must either start with \"rom:/\" or \"sd:/\"", error),
             Error::GithubError(_) => eprintln!("{}: failed to get the latest release from github", error),
-            //Error::InvalidRepo => eprintln!("{}: repos must be in the form of `{{user}}/{{repo}}`", error),
+            Error::InvalidRepo => eprintln!("{}: repos must be in the form of `{{user}}/{{repo}}`", error),
             //Error::HostNotSupported => eprintln!("{}: your host platform is not supported.", error),
             Error::DownloadFailed => eprintln!("{}: the update failed to download.", error),
             Error::RustupNotFound => eprintln!("{}: rustup could not be executed, make sure it is installed.", error),
@@ -476,9 +789,37 @@ fn main() {
             Error::ToolchainCopyFailed => eprintln!("{}: could not copy the backing toolchain", error),
             Error::GitNotInstalled => eprintln!("{}: git is not installed, please install it", error),
             Error::StdCloneFailed => eprintln!("{}: std fork failed to clone", error),
+            Error::InvalidToolchainName(name) => eprintln!("{}: '{}' is not a valid toolchain name, only letters, numbers, `-`, `_` and `.` are allowed", error, name),
+            Error::ReservedToolchainName(name) => eprintln!("{}: '{}' can't be used as a toolchain name, it is the directory of the '{}' toolchain", error, name, toolchain::DEFAULT_TOOLCHAIN),
+            Error::ToolchainNotInstalled(name) => eprintln!("{}: the toolchain '{}' is not installed, install it with `cargo skyline toolchain install {}`", error, name, name),
+            Error::RemoveDefaultToolchain(name) => eprintln!("{}: '{}' is the default toolchain, set another default before removing it", error, name),
+            Error::ToolchainIncomplete(name) => eprintln!("{}: the toolchain '{}' was only partially installed, fix it with `cargo skyline toolchain repair {}`", error, name, name),
+            Error::ToolchainVerifyFailed(name, count) => eprintln!("{}: {} problem(s) were found with '{}', fix them with `cargo skyline toolchain repair {}`", error, count, name, name),
+            Error::NeedsConfirmation(prompt) => eprintln!("{}: cargo-skyline is running non-interactively and can't ask \"{}\", pass --yes to accept", error, prompt),
+            Error::UnknownGame(game) => eprintln!("{}: '{}' is not a known game or a 16 digit title ID", error, game),
+            Error::NoProjectName => eprintln!("{}: a plugin name is required when not running interactively", error),
+            Error::UnknownTemplate(template) => eprintln!("{}: '{}' is not a built-in template, local directory or git url", error, template),
+            Error::TemplateCloneFailed(url) => eprintln!("{}: template could not be cloned from '{}'", error, url),
+            Error::BadToolchainArchive => eprintln!("{}: toolchain archive could not be read, it should be created with `cargo skyline toolchain export`", error),
             Error::NoBaseCommit => eprintln!("{}: No base rust-src commit was found, cannot determined correct nightly.", error),
             Error::ProjectAlreadyExists => eprintln!("{}: a folder with that name already exists", error),
             Error::FailCreateProject => eprintln!("{}: project files could not be written to disk", error),
+            Error::UnresolvedImports(count) => eprintln!("{}: {} import(s) could not be resolved, the plugin would fail to load", error, count),
+            Error::InvalidDependency(name) => eprintln!("{}: plugin dependency '{}' must have either a `url` or a `repo` in the form of `{{user}}/{{repo}}`", error, name),
+            Error::NoMatchingAsset(name, pattern) => eprintln!("{}: no asset matching '{}' was found in the release for plugin dependency '{}'", error, pattern, name),
+            Error::BadDependencyZip(name) => eprintln!("{}: failed to extract plugin dependency '{}'. Either the zip is corrupted or is missing files listed in `extract`.", error, name),
+            Error::PackageVerifyFailed(count) => eprintln!("{}: {} problem(s) were found with the package", error, count),
+            Error::FlatLayoutMultipleTitles => eprintln!("{}: the flat layout can only hold a single title, pass --split to build a package per title ID", error),
+            Error::BadNpdm => eprintln!("{}: file could not be parsed as an NPDM", error),
+            Error::BadNpdmDescription(err) => eprintln!("{}: NPDM description is invalid: {}", error, err),
+            Error::NpdmDescriptionMissing(path) => eprintln!("{}: NPDM description '{}' could not be read", error, path.display()),
+            Error::BadLockFile => eprintln!("{}: Skyline.lock could not be read or written", error),
+            Error::LockMismatch(name) => eprintln!("{}: '{}' does not match Skyline.lock, pass --update-lock to accept the new version", error, name),
+            Error::ChecksumMismatch(name, expected, actual) => eprintln!("{}: sha256 of '{}' does not match Cargo.toml\n   expected: {}\n   actual:   {}", error, name, expected, actual),
+            Error::NotCached(url) => eprintln!("{}: '{}' has not been cached, it must be downloaded before it can be used offline", error, url),
+            Error::BadTargetJson => eprintln!("{}: The target JSON could not be parsed, check `custom-target-json` and `target-overrides` in Cargo.toml", error),
+            Error::TargetFileMissing(path) => eprintln!("{}: Target file '{}' specified in Cargo.toml not found at the specified path.", error, path.display()),
+            Error::BadBinaryFormat => eprintln!("{}: file could not be parsed as an ELF, NRO or NSO", error),
             Error::PackageResourceMissing(path) => eprintln!("{}: Package resource '{}' specified in Cargo.toml not found at the specified path.", error, path.display()),
         }
 
@@ -1,39 +1,469 @@
 use crate::error::{Error, Result};
+use crate::prompt;
+use dialoguer::{Confirm, Input, Select};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use walkdir::WalkDir;
 
 macro_rules! files {
-    ($($path:literal),*) => {
+    ($template:literal: $($path:literal),*) => {
         &[$(
             (
                 $path,
-                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/new-project-template/", $path, ".template")),
+                include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/new-project-template/", $template, "/", $path, ".template")),
             )
         ),*]
     };
 }
 
-const FILES: &[(&str, &str)] = files!["Cargo.toml", "src/lib.rs"];
+type TemplateFiles = &'static [(&'static str, &'static str)];
 
-pub fn new_plugin(name: String) -> Result<()> {
-    crate::update_std::check_std_installed()?;
+const TEMPLATES: &[(&str, TemplateFiles)] = &[
+    ("hello", files!["hello": "Cargo.toml", "src/lib.rs"]),
+    ("inline-hook", files!["inline-hook": "Cargo.toml", "src/lib.rs"]),
+    ("symbol-hook", files!["symbol-hook": "Cargo.toml", "src/lib.rs"]),
+    ("subsdk", files!["subsdk": "Cargo.toml", "src/lib.rs"]),
+    ("library", files!["library": "Cargo.toml", "src/lib.rs"]),
+    (
+        "workspace",
+        files![
+            "workspace": "Cargo.toml",
+            "plugins/first/Cargo.toml",
+            "plugins/first/src/lib.rs",
+            "plugins/second/Cargo.toml",
+            "plugins/second/src/lib.rs"
+        ]
+    ),
+];
 
-    let plugin_folder = Path::new(".").join(&name);
+/// Descriptions of the built-in templates, shown when picking one interactively
+const TEMPLATE_DESCRIPTIONS: &[(&str, &str)] = &[
+    ("hello", "Plugin which prints a message when loaded"),
+    ("inline-hook", "Plugin hooking an instruction by offset"),
+    ("symbol-hook", "Plugin replacing a function by symbol"),
+    ("subsdk", "Project built as a subsdk rather than a plugin"),
+    ("library", "Library for other plugins to depend on"),
+    ("workspace", "Workspace containing multiple plugins"),
+];
+
+/// Commonly modded games, as (name for `--game`, display name, title ID)
+const GAMES: &[(&str, &str, &str)] = &[
+    ("smash-ultimate", "Super Smash Bros. Ultimate", "01006A800016E000"),
+    ("botw", "The Legend of Zelda: Breath of the Wild", "01007EF00011E000"),
+    ("totk", "The Legend of Zelda: Tears of the Kingdom", "0100F2C0115B6000"),
+    ("odyssey", "Super Mario Odyssey", "0100000000010000"),
+    ("wonder", "Super Mario Bros. Wonder", "010015100B514000"),
+    ("mk8dx", "Mario Kart 8 Deluxe", "0100152000022000"),
+    ("acnh", "Animal Crossing: New Horizons", "01006F8002326000"),
+    ("splatoon2", "Splatoon 2", "01003BC0000A0000"),
+    ("splatoon3", "Splatoon 3", "0100C2500FC20000"),
+    ("sword", "Pokémon Sword", "0100ABF008968000"),
+    ("shield", "Pokémon Shield", "01008DB008C2C000"),
+    ("arceus", "Pokémon Legends: Arceus", "01001F5010DFA000"),
+    ("scarlet", "Pokémon Scarlet", "0100A3D008C5C000"),
+    ("violet", "Pokémon Violet", "01008F6008C5E000"),
+    ("xc2", "Xenoblade Chronicles 2", "0100E95004038000"),
+    ("fe3h", "Fire Emblem: Three Houses", "010055D009F78000"),
+    ("metroid-dread", "Metroid Dread", "010093801237C000"),
+    ("kirby", "Kirby and the Forgotten Land", "01004D300C5AE000"),
+];
+
+const DEFAULT_TITLE_ID: &str = "01006A800016E000";
+const DEFAULT_GAME: &str = "Super Smash Bros. Ultimate";
+
+const RESTART_PLUGIN_DEPENDENCY: &str =
+    r#"{ name = "librestart_plugin.nro", repo = "jam1garner/restart-plugin" }"#;
+
+const LOGGER: &str = r#"use log::{LevelFilter, Log, Metadata, Record};
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    // Skyline sends anything printed to `cargo skyline listen`
+    fn log(&self, record: &Record) {
+        println!("[{}] {} {}", env!("CARGO_PKG_NAME"), record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    let _ = log::set_logger(&Logger).map(|_| log::set_max_level(LevelFilter::Info));
+}
+"#;
+
+/// Values substituted into templates, as `{{key}}` in both file contents and paths
+pub struct Placeholders {
+    pub name: String,
+    pub title_id: String,
+    pub game: String,
+}
+
+impl Placeholders {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            title_id: DEFAULT_TITLE_ID.to_owned(),
+            game: DEFAULT_GAME.to_owned(),
+        }
+    }
+
+    /// Set the game from either its name in the built-in table or a title ID
+    fn set_game(&mut self, game: &str) -> Result<()> {
+        let known = GAMES.iter().find(|(name, _, title_id)| {
+            name.eq_ignore_ascii_case(game) || title_id.eq_ignore_ascii_case(game)
+        });
+
+        match known {
+            Some((_, display_name, title_id)) => {
+                self.title_id = title_id.to_string();
+                self.game = display_name.to_string();
+            }
+            None if is_title_id(game) => {
+                self.title_id = game.to_uppercase();
+                self.game = "Unknown game".to_owned();
+            }
+            None => return Err(Error::UnknownGame(game.to_owned())),
+        }
+
+        Ok(())
+    }
+
+    fn substitute(&self, contents: &str) -> String {
+        let crate_name = self.name.replace('-', "_");
+
+        contents
+            .replace("{{name}}", &self.name)
+            .replace("{{crate_name}}", &crate_name)
+            .replace("{{title_id}}", &self.title_id)
+            .replace("{{game}}", &self.game)
+            // Used by templates written before placeholders were added
+            .replace("skyline_rs_template", &self.name)
+    }
+}
+
+fn is_title_id(title_id: &str) -> bool {
+    title_id.len() == 16 && title_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Extra setup for the project beyond what the template contains
+#[derive(Default)]
+pub struct Extras {
+    pub logging: bool,
+    pub restart_plugin: bool,
+    pub package_resources: bool,
+}
+
+/// Insert lines at the end of a TOML table, leaving the rest of the file (and its comments) as-is
+fn insert_into_table(manifest: &mut String, table: &str, lines: &str) -> bool {
+    let header = format!("[{}]\n", table);
+
+    let start = match manifest.find(&header) {
+        Some(start) => start + header.len(),
+        None => return false,
+    };
+
+    let end = manifest[start..]
+        .find("\n[")
+        .map(|end| start + end + 1)
+        .unwrap_or(manifest.len());
+
+    // Keep any blank line separating the table from the next one
+    let end = if manifest[..end].ends_with("\n\n") { end - 1 } else { end };
+
+    manifest.insert_str(end, lines);
+
+    true
+}
+
+/// Apply the extras to every plugin in the template, meaning any crate with skyline metadata.
+/// Placeholders in what's added are substituted along with the rest of the template.
+fn apply_extras(files: &mut Vec<(String, Vec<u8>)>, extras: &Extras) {
+    let mut added = Vec::new();
+
+    for (path, contents) in files.iter_mut() {
+        let text = match std::str::from_utf8(contents) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+
+        let dir = path.rsplit_once('/').map(|(dir, _)| format!("{}/", dir)).unwrap_or_default();
+
+        if path.ends_with("Cargo.toml") && text.contains("[package.metadata.skyline]\n") {
+            let mut manifest = text.to_owned();
+            let mut metadata = String::new();
+
+            if extras.restart_plugin {
+                metadata += &format!("plugin-dependencies = [{}]\n", RESTART_PLUGIN_DEPENDENCY);
+            }
+
+            if extras.package_resources {
+                // Each plugin of a workspace gets its own folder in romfs
+                let package_name = text
+                    .lines()
+                    .find_map(|line| line.strip_prefix("name = \"")?.strip_suffix('"'))
+                    .unwrap_or("{{name}}");
+
+                metadata += &format!(
+                    "package-resources = [\n    {{ local = \"resources/\", package = \"atmosphere/contents/{{{{title_id}}}}/romfs/{}/\" }},\n]\n",
+                    package_name
+                );
+
+                added.push((
+                    format!("{}resources/config.toml", dir),
+                    format!("# Packaged to rom:/{}/config.toml\n", package_name).into_bytes(),
+                ));
+            }
+
+            insert_into_table(&mut manifest, "package.metadata.skyline", &metadata);
+
+            if extras.logging {
+                insert_into_table(&mut manifest, "dependencies", "log = \"0.4\"\n");
+            }
+
+            *contents = manifest.into_bytes();
+        } else if extras.logging && path.ends_with("src/lib.rs") && text.contains("#[skyline::main") {
+            let lib = format!("mod logger;\n\n{}", text).replacen(
+                "pub fn main() {\n",
+                "pub fn main() {\n    logger::init();\n",
+                1,
+            );
+
+            *contents = lib.into_bytes();
+            added.push((format!("{}logger.rs", dir), LOGGER.as_bytes().to_vec()));
+        }
+    }
+
+    files.extend(added);
+}
+
+fn is_git_url(template: &str) -> bool {
+    template.contains("://") || template.starts_with("git@") || template.ends_with(".git")
+}
+
+/// Read every file of a template directory, skipping its git history. A `.template` extension is
+/// removed so that templates can contain manifests without cargo treating them as crates.
+fn read_template_dir(dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+
+    let entries = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+
+    for entry in entries {
+        let entry = entry.map_err(|_| Error::FailCreateProject)?;
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path().strip_prefix(dir).unwrap().to_string_lossy().replace('\\', "/");
+        let path = path.strip_suffix(".template").unwrap_or(&path).to_owned();
+
+        files.push((path, fs::read(entry.path())?));
+    }
+
+    Ok(files)
+}
+
+fn clone_template(url: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("cargo-skyline-template-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let success = Command::new("git")
+        .args(["clone", "--depth", "1", "-q", url])
+        .arg(&dir)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|_| Error::GitNotInstalled)?
+        .success();
+
+    if success {
+        Ok(dir)
+    } else {
+        let _ = fs::remove_dir_all(&dir);
+        Err(Error::TemplateCloneFailed(url.to_owned()))
+    }
+}
+
+/// Get the files of a template, either one of the built-in templates, a local directory or a git
+/// url
+fn template_files(template: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    if let Some((_, files)) = TEMPLATES.iter().find(|(name, _)| *name == template) {
+        return Ok(files
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.as_bytes().to_vec()))
+            .collect());
+    }
+
+    if Path::new(template).is_dir() {
+        return read_template_dir(Path::new(template));
+    }
+
+    if is_git_url(template) {
+        let dir = clone_template(template)?;
+        let files = read_template_dir(&dir);
+        let _ = fs::remove_dir_all(&dir);
+
+        return files;
+    }
+
+    Err(Error::UnknownTemplate(template.to_owned()))
+}
+
+/// Ask for anything not given on the command line
+fn wizard(
+    template: Option<String>,
+    game: Option<String>,
+    extras: &mut Extras,
+) -> Result<(String, Placeholders)> {
+    let name = Input::<String>::new()
+        .with_prompt("Plugin name")
+        .validate_with(|name: &String| {
+            if Path::new(name).exists() {
+                Err("a folder with that name already exists")
+            } else {
+                Ok(())
+            }
+        })
+        .interact_text()?;
+
+    let mut placeholders = Placeholders::new(&name);
+
+    match game {
+        Some(game) => placeholders.set_game(&game)?,
+        None => {
+            let mut items: Vec<String> = GAMES
+                .iter()
+                .map(|(_, name, title_id)| format!("{} ({})", name, title_id))
+                .collect();
+            items.push("Other (enter a title ID)".to_owned());
+
+            let choice = Select::new()
+                .with_prompt("Game")
+                .items(&items)
+                .default(0)
+                .interact()?;
+
+            match GAMES.get(choice) {
+                Some((_, display_name, title_id)) => {
+                    placeholders.title_id = title_id.to_string();
+                    placeholders.game = display_name.to_string();
+                }
+                None => {
+                    let title_id = Input::<String>::new()
+                        .with_prompt("Title ID")
+                        .validate_with(|title_id: &String| {
+                            if is_title_id(title_id) {
+                                Ok(())
+                            } else {
+                                Err("title IDs are 16 hex digits")
+                            }
+                        })
+                        .interact_text()?;
+
+                    placeholders.set_game(&title_id)?;
+                }
+            }
+        }
+    }
+
+    let template = match template {
+        Some(template) => template,
+        None => {
+            let items: Vec<String> = TEMPLATE_DESCRIPTIONS
+                .iter()
+                .map(|(name, description)| format!("{:<12} {}", name, description))
+                .collect();
+
+            let choice = Select::new()
+                .with_prompt("Kind of plugin")
+                .items(&items)
+                .default(0)
+                .interact()?;
+
+            TEMPLATE_DESCRIPTIONS[choice].0.to_owned()
+        }
+    };
+
+    let ask = |prompt: &str, given: bool| -> Result<bool> {
+        if given {
+            return Ok(true);
+        }
+
+        Ok(Confirm::new().with_prompt(prompt).default(false).interact()?)
+    };
+
+    extras.logging = ask("Set up logging with the `log` crate?", extras.logging)?;
+    extras.restart_plugin = ask(
+        "Install restart-plugin alongside it, for `cargo skyline run --restart`?",
+        extras.restart_plugin,
+    )?;
+    extras.package_resources = ask(
+        "Bundle files from a resources folder when packaging?",
+        extras.package_resources,
+    )?;
+
+    Ok((template, placeholders))
+}
+
+pub fn new_plugin(
+    name: Option<String>,
+    template: Option<String>,
+    game: Option<String>,
+    mut extras: Extras,
+) -> Result<()> {
+    // Without a name, fall back to asking for everything
+    let (template, placeholders) = match name {
+        Some(name) => {
+            let mut placeholders = Placeholders::new(&name);
+
+            if let Some(game) = game {
+                placeholders.set_game(&game)?;
+            }
+
+            (template.unwrap_or_else(|| "hello".to_owned()), placeholders)
+        }
+        None if prompt::is_interactive() => wizard(template, game, &mut extras)?,
+        None => return Err(Error::NoProjectName),
+    };
+
+    create_project(&template, &placeholders, &extras)
+}
+
+pub fn create_project(template: &str, placeholders: &Placeholders, extras: &Extras) -> Result<()> {
+    crate::update_std::check_std_installed(&crate::toolchain::default_toolchain())?;
+
+    let name = &placeholders.name;
+
+    let plugin_folder = Path::new(".").join(name);
     if plugin_folder.exists() {
         return Err(Error::ProjectAlreadyExists);
     }
 
+    let mut files = template_files(template)?;
+    apply_extras(&mut files, extras);
+
     fs::create_dir(&plugin_folder).map_err(|_| Error::FailCreateProject)?;
 
-    for (file, contents) in FILES {
-        let path = plugin_folder.join(file);
+    for (file, contents) in files {
+        let path = plugin_folder.join(placeholders.substitute(&file));
         if let Some(parent) = path.parent() {
             let _ = fs::create_dir_all(parent);
         }
 
-        fs::write(path, contents.replace("skyline_rs_template", &name))
-            .map_err(|_| Error::FailCreateProject)?;
+        // Anything which isn't text, such as images, is copied as-is
+        let contents = match String::from_utf8(contents) {
+            Ok(contents) => placeholders.substitute(&contents).into_bytes(),
+            Err(err) => err.into_bytes(),
+        };
+
+        fs::write(path, contents).map_err(|_| Error::FailCreateProject)?;
     }
 
     let success = Command::new("git")
@@ -0,0 +1,529 @@
+use crate::cargo_info::Metadata;
+use crate::error::{Error, Result};
+use crate::installer::TEMPLATE_NPDM;
+use crate::update_std::merge_json;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+pub enum NpdmCommand {
+    #[structopt(about = "Build an NPDM from a JSON or TOML description")]
+    Generate {
+        #[structopt(about = "Path of the description, any fields left out are taken from Skyline's NPDM")]
+        description: PathBuf,
+
+        #[structopt(short, long, about = "Title ID to use rather than the description's")]
+        title_id: Option<String>,
+
+        #[structopt(short, long, default_value = "main.npdm", about = "Path to write the NPDM to")]
+        out_path: PathBuf,
+    },
+    #[structopt(about = "Print the description of an existing NPDM")]
+    Dump {
+        #[structopt(about = "Path of the NPDM, defaults to the one used by the current plugin")]
+        npdm: Option<PathBuf>,
+
+        #[structopt(long, about = "Print the description as TOML rather than JSON")]
+        toml: bool,
+    },
+}
+
+/// A number written either as an integer or as a hex string, as values such as permissions don't
+/// fit in a TOML integer
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Hex(pub u64);
+
+impl Serialize for Hex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:X}", self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Hex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Int(u64),
+            Str(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Int(value) => Ok(Hex(value)),
+            Repr::Str(value) => {
+                u64::from_str_radix(value.trim_start_matches("0x").trim_start_matches("0X"), 16)
+                    .map(Hex)
+                    .map_err(|_| serde::de::Error::custom(format!("'{}' is not a hex number", value)))
+            }
+        }
+    }
+}
+
+/// Title IDs are always written out in full, as they are everywhere else
+fn serialize_title_id<S: Serializer>(title_id: &Hex, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:016X}", title_id.0))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FsAccess {
+    pub permissions: Hex,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum KernelCapability {
+    KernelFlags {
+        highest_thread_priority: u8,
+        lowest_thread_priority: u8,
+        highest_cpu_id: u8,
+        lowest_cpu_id: u8,
+    },
+    /// Syscalls to allow, mapping a name for each to its number
+    Syscalls(BTreeMap<String, Hex>),
+    ApplicationType(u8),
+    MinKernelVersion(Hex),
+    HandleTableSize(u16),
+    DebugFlags {
+        allow_debug: bool,
+        force_debug: bool,
+    },
+    /// A descriptor which is passed through as-is
+    Raw(Hex),
+}
+
+/// Everything needed to build an NPDM, in the same format as switch-tools' npdmtool. Tables are
+/// kept last so the description can be written as TOML.
+#[derive(Serialize, Deserialize)]
+pub struct NpdmDescription {
+    pub name: String,
+    #[serde(serialize_with = "serialize_title_id")]
+    pub title_id: Hex,
+    #[serde(serialize_with = "serialize_title_id")]
+    pub title_id_range_min: Hex,
+    #[serde(serialize_with = "serialize_title_id")]
+    pub title_id_range_max: Hex,
+    pub main_thread_stack_size: Hex,
+    pub main_thread_priority: u8,
+    pub default_cpu_id: u8,
+    pub system_resource_size: Hex,
+    pub version: u32,
+    pub is_64_bit: bool,
+    pub address_space_type: u8,
+    pub is_retail: bool,
+    pub pool_partition: u8,
+    pub service_access: Vec<String>,
+    pub service_host: Vec<String>,
+    pub filesystem_access: FsAccess,
+    pub kernel_capabilities: Vec<KernelCapability>,
+}
+
+const META_SIZE: usize = 0x80;
+const ACID_HEADER_SIZE: usize = 0x240;
+const ACI0_HEADER_SIZE: usize = 0x40;
+const FAC_SIZE: usize = 0x2C;
+const FAH_SIZE: usize = 0x1C;
+
+fn align(value: usize) -> usize {
+    (value + 0xF) & !0xF
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn get_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::BadNpdm)
+}
+
+fn get_u64(data: &[u8], offset: usize) -> Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(Error::BadNpdm)
+}
+
+/// Service access control, each name prefixed by its length and whether it is hosted
+fn build_sac(desc: &NpdmDescription) -> Result<Vec<u8>> {
+    let mut sac = Vec::new();
+
+    let services = desc
+        .service_host
+        .iter()
+        .map(|name| (name, 0x80))
+        .chain(desc.service_access.iter().map(|name| (name, 0)));
+
+    for (name, host) in services {
+        if name.is_empty() || name.len() > 8 {
+            return Err(Error::BadNpdmDescription(format!(
+                "service name '{}' must be 1-8 characters",
+                name
+            )));
+        }
+
+        sac.push(host | (name.len() as u8 - 1));
+        sac.extend_from_slice(name.as_bytes());
+    }
+
+    Ok(sac)
+}
+
+fn build_kc(desc: &NpdmDescription) -> Vec<u8> {
+    let mut descriptors = Vec::new();
+
+    for capability in &desc.kernel_capabilities {
+        match capability {
+            KernelCapability::KernelFlags {
+                highest_thread_priority,
+                lowest_thread_priority,
+                highest_cpu_id,
+                lowest_cpu_id,
+            } => descriptors.push(
+                (*highest_cpu_id as u32) << 24
+                    | (*lowest_cpu_id as u32) << 16
+                    | (*lowest_thread_priority as u32 & 0x3F) << 10
+                    | (*highest_thread_priority as u32 & 0x3F) << 4
+                    | 0x7,
+            ),
+            KernelCapability::Syscalls(syscalls) => {
+                // Each descriptor holds a mask for 24 consecutive syscalls
+                let mut masks = BTreeMap::new();
+                for Hex(id) in syscalls.values() {
+                    *masks.entry(id / 24).or_insert(0u32) |= 1 << (id % 24);
+                }
+
+                for (index, mask) in masks {
+                    descriptors.push((index as u32) << 29 | mask << 5 | 0xF);
+                }
+            }
+            KernelCapability::ApplicationType(kind) => {
+                descriptors.push((*kind as u32 & 0x7) << 14 | 0x1FFF)
+            }
+            KernelCapability::MinKernelVersion(Hex(version)) => {
+                descriptors.push((*version as u32) << 15 | 0x3FFF)
+            }
+            KernelCapability::HandleTableSize(size) => {
+                descriptors.push((*size as u32 & 0x3FF) << 16 | 0x7FFF)
+            }
+            KernelCapability::DebugFlags {
+                allow_debug,
+                force_debug,
+            } => descriptors.push((*force_debug as u32) << 18 | (*allow_debug as u32) << 17 | 0xFFFF),
+            KernelCapability::Raw(Hex(descriptor)) => descriptors.push(*descriptor as u32),
+        }
+    }
+
+    descriptors.into_iter().flat_map(u32::to_le_bytes).collect()
+}
+
+/// Build the binary NPDM for a description
+pub fn build(desc: &NpdmDescription) -> Result<Vec<u8>> {
+    if desc.name.len() > 0x10 {
+        return Err(Error::BadNpdmDescription(format!(
+            "name '{}' must be at most 16 characters",
+            desc.name
+        )));
+    }
+
+    let sac = build_sac(desc)?;
+    let kc = build_kc(desc);
+    let permissions = desc.filesystem_access.permissions.0;
+
+    // ACID, left unsigned
+    let acid_sac = align(ACID_HEADER_SIZE + FAC_SIZE);
+    let acid_kc = align(acid_sac + sac.len());
+    let mut acid = vec![0; acid_kc + kc.len()];
+
+    acid[0x200..0x204].copy_from_slice(b"ACID");
+    let acid_size = acid.len();
+    put_u32(&mut acid, 0x204, (acid_size - 0x100) as u32);
+    put_u32(
+        &mut acid,
+        0x20C,
+        desc.is_retail as u32 | (desc.pool_partition as u32 & 0xF) << 2,
+    );
+    put_u64(&mut acid, 0x210, desc.title_id_range_min.0);
+    put_u64(&mut acid, 0x218, desc.title_id_range_max.0);
+    put_u32(&mut acid, 0x220, ACID_HEADER_SIZE as u32);
+    put_u32(&mut acid, 0x224, FAC_SIZE as u32);
+    put_u32(&mut acid, 0x228, acid_sac as u32);
+    put_u32(&mut acid, 0x22C, sac.len() as u32);
+    put_u32(&mut acid, 0x230, acid_kc as u32);
+    put_u32(&mut acid, 0x234, kc.len() as u32);
+
+    acid[ACID_HEADER_SIZE] = 1;
+    put_u64(&mut acid, ACID_HEADER_SIZE + 4, permissions);
+    acid[acid_sac..acid_sac + sac.len()].copy_from_slice(&sac);
+    acid[acid_kc..].copy_from_slice(&kc);
+
+    // ACI0
+    let aci0_sac = align(ACI0_HEADER_SIZE + FAH_SIZE);
+    let aci0_kc = align(aci0_sac + sac.len());
+    let mut aci0 = vec![0; aci0_kc + kc.len()];
+
+    aci0[..4].copy_from_slice(b"ACI0");
+    put_u64(&mut aci0, 0x10, desc.title_id.0);
+    put_u32(&mut aci0, 0x20, ACI0_HEADER_SIZE as u32);
+    put_u32(&mut aci0, 0x24, FAH_SIZE as u32);
+    put_u32(&mut aci0, 0x28, aci0_sac as u32);
+    put_u32(&mut aci0, 0x2C, sac.len() as u32);
+    put_u32(&mut aci0, 0x30, aci0_kc as u32);
+    put_u32(&mut aci0, 0x34, kc.len() as u32);
+
+    aci0[ACI0_HEADER_SIZE] = 1;
+    put_u64(&mut aci0, ACI0_HEADER_SIZE + 4, permissions);
+    put_u32(&mut aci0, ACI0_HEADER_SIZE + 0xC, FAH_SIZE as u32);
+    put_u32(&mut aci0, ACI0_HEADER_SIZE + 0x14, FAH_SIZE as u32);
+    aci0[aci0_sac..aci0_sac + sac.len()].copy_from_slice(&sac);
+    aci0[aci0_kc..].copy_from_slice(&kc);
+
+    // META
+    let aci0_offset = align(META_SIZE + acid.len());
+    let mut npdm = vec![0; aci0_offset + aci0.len()];
+
+    npdm[..4].copy_from_slice(b"META");
+    npdm[0xC] = desc.is_64_bit as u8 | (desc.address_space_type & 0x7) << 1;
+    npdm[0xE] = desc.main_thread_priority;
+    npdm[0xF] = desc.default_cpu_id;
+    put_u32(&mut npdm, 0x14, desc.system_resource_size.0 as u32);
+    put_u32(&mut npdm, 0x18, desc.version);
+    put_u32(&mut npdm, 0x1C, desc.main_thread_stack_size.0 as u32);
+    npdm[0x20..0x20 + desc.name.len()].copy_from_slice(desc.name.as_bytes());
+    put_u32(&mut npdm, 0x70, aci0_offset as u32);
+    put_u32(&mut npdm, 0x74, aci0.len() as u32);
+    put_u32(&mut npdm, 0x78, META_SIZE as u32);
+    put_u32(&mut npdm, 0x7C, acid.len() as u32);
+
+    npdm[META_SIZE..META_SIZE + acid.len()].copy_from_slice(&acid);
+    npdm[aci0_offset..].copy_from_slice(&aci0);
+
+    Ok(npdm)
+}
+
+fn parse_sac(sac: &[u8]) -> Result<(Vec<String>, Vec<String>)> {
+    let (mut access, mut host) = (Vec::new(), Vec::new());
+
+    let mut i = 0;
+    while i < sac.len() {
+        let control = sac[i];
+        let len = (control & 0x7) as usize + 1;
+        let name = sac.get(i + 1..i + 1 + len).ok_or(Error::BadNpdm)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+
+        if control & 0x80 != 0 {
+            host.push(name);
+        } else {
+            access.push(name);
+        }
+
+        i += 1 + len;
+    }
+
+    Ok((access, host))
+}
+
+fn parse_kc(kc: &[u8]) -> Vec<KernelCapability> {
+    let mut capabilities = Vec::new();
+    let mut syscalls = BTreeMap::new();
+    let mut syscalls_index = None;
+
+    for descriptor in kc.chunks_exact(4) {
+        let descriptor = u32::from_le_bytes(descriptor.try_into().unwrap());
+
+        // The type of a descriptor is given by the number of trailing ones
+        let capability = match descriptor.trailing_ones() {
+            3 => KernelCapability::KernelFlags {
+                highest_thread_priority: (descriptor >> 4 & 0x3F) as u8,
+                lowest_thread_priority: (descriptor >> 10 & 0x3F) as u8,
+                lowest_cpu_id: (descriptor >> 16 & 0xFF) as u8,
+                highest_cpu_id: (descriptor >> 24) as u8,
+            },
+            4 => {
+                syscalls_index.get_or_insert(capabilities.len());
+
+                let index = descriptor >> 29;
+                for bit in 0..24 {
+                    if descriptor >> 5 & (1 << bit) != 0 {
+                        let id = (index * 24 + bit) as u64;
+                        syscalls.insert(format!("svc{:02X}", id), Hex(id));
+                    }
+                }
+                continue;
+            }
+            13 => KernelCapability::ApplicationType((descriptor >> 14 & 0x7) as u8),
+            14 => KernelCapability::MinKernelVersion(Hex((descriptor >> 15) as u64)),
+            15 => KernelCapability::HandleTableSize((descriptor >> 16 & 0x3FF) as u16),
+            16 => KernelCapability::DebugFlags {
+                allow_debug: descriptor >> 17 & 1 != 0,
+                force_debug: descriptor >> 18 & 1 != 0,
+            },
+            _ => KernelCapability::Raw(Hex(descriptor as u64)),
+        };
+
+        capabilities.push(capability);
+    }
+
+    // Syscalls are merged into one capability, kept where the first descriptor was
+    if let Some(index) = syscalls_index {
+        capabilities.insert(index, KernelCapability::Syscalls(syscalls));
+    }
+
+    capabilities
+}
+
+/// Read the description of a binary NPDM
+pub fn parse(npdm: &[u8]) -> Result<NpdmDescription> {
+    if npdm.get(..4) != Some(b"META") {
+        return Err(Error::BadNpdm);
+    }
+
+    let section = |offset, size| -> Result<&[u8]> {
+        let (offset, size) = (get_u32(npdm, offset)? as usize, get_u32(npdm, size)? as usize);
+        npdm.get(offset..offset + size).ok_or(Error::BadNpdm)
+    };
+
+    let aci0 = section(0x70, 0x74)?;
+    let acid = section(0x78, 0x7C)?;
+
+    if aci0.get(..4) != Some(b"ACI0") || acid.get(0x200..0x204) != Some(b"ACID") {
+        return Err(Error::BadNpdm);
+    }
+
+    let aci0_section = |offset, size| -> Result<&[u8]> {
+        let (offset, size) = (get_u32(aci0, offset)? as usize, get_u32(aci0, size)? as usize);
+        aci0.get(offset..offset + size).ok_or(Error::BadNpdm)
+    };
+
+    let fah = aci0_section(0x20, 0x24)?;
+    let (service_access, service_host) = parse_sac(aci0_section(0x28, 0x2C)?)?;
+    let kernel_capabilities = parse_kc(aci0_section(0x30, 0x34)?);
+
+    let name = &npdm[0x20..0x30];
+    let name_len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    let acid_flags = get_u32(acid, 0x20C)?;
+
+    Ok(NpdmDescription {
+        name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+        title_id: Hex(get_u64(aci0, 0x10)?),
+        title_id_range_min: Hex(get_u64(acid, 0x210)?),
+        title_id_range_max: Hex(get_u64(acid, 0x218)?),
+        main_thread_stack_size: Hex(get_u32(npdm, 0x1C)? as u64),
+        main_thread_priority: npdm[0xE],
+        default_cpu_id: npdm[0xF],
+        system_resource_size: Hex(get_u32(npdm, 0x14)? as u64),
+        version: get_u32(npdm, 0x18)?,
+        is_64_bit: npdm[0xC] & 1 != 0,
+        address_space_type: npdm[0xC] >> 1 & 0x7,
+        is_retail: acid_flags & 1 != 0,
+        pool_partition: (acid_flags >> 2 & 0xF) as u8,
+        service_access,
+        service_host,
+        filesystem_access: FsAccess {
+            permissions: Hex(get_u64(fah, 4)?),
+        },
+        kernel_capabilities,
+    })
+}
+
+fn read_description(path: &Path) -> Result<serde_json::Value> {
+    let contents =
+        fs::read_to_string(path).map_err(|_| Error::NpdmDescriptionMissing(path.to_owned()))?;
+
+    let description = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&contents).map_err(|err| Error::BadNpdmDescription(err.to_string()))?
+    } else {
+        serde_json::from_str(&contents).map_err(|err| Error::BadNpdmDescription(err.to_string()))?
+    };
+
+    Ok(description)
+}
+
+/// Whether a `custom-npdm` path refers to a description rather than a binary NPDM
+pub fn is_description(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "json" || ext == "toml")
+}
+
+/// Build an NPDM from a description file, taking anything it leaves out from Skyline's NPDM. The
+/// given title ID takes priority over the description's, as plugins for several titles share one.
+pub fn build_from_description(path: &Path, title_id: Option<&str>) -> Result<Vec<u8>> {
+    let description = read_description(path)?;
+
+    let mut base = serde_json::to_value(parse(TEMPLATE_NPDM)?).unwrap();
+
+    merge_json(&mut base, &description);
+
+    if let Some(title_id) = title_id {
+        base["title_id"] = serde_json::Value::String(title_id.to_owned());
+    }
+
+    let description: NpdmDescription = serde_json::from_value(base)
+        .map_err(|err| Error::BadNpdmDescription(err.to_string()))?;
+
+    build(&description)
+}
+
+/// The custom NPDM a plugin uses for the given title, built from a description if needed
+pub fn custom_npdm(metadata: &Metadata, title_id: &str) -> Result<Option<Vec<u8>>> {
+    match metadata.npdm_path.as_deref().map(Path::new) {
+        Some(path) if is_description(path) => Ok(Some(build_from_description(path, Some(title_id))?)),
+        Some(path) => Ok(Some(fs::read(path).map_err(|_| Error::NoNpdmFileFound)?)),
+        None => Ok(None),
+    }
+}
+
+fn dump(npdm: Option<PathBuf>, toml: bool) -> Result<()> {
+    let data = match npdm {
+        Some(path) => fs::read(path)?,
+        None => {
+            let metadata = crate::cargo_info::get_metadata()?;
+            let title_id = metadata.title_id.clone().ok_or(Error::NoTitleId)?;
+
+            custom_npdm(&metadata, &title_id)?
+                .unwrap_or_else(|| crate::installer::generate_npdm(&title_id))
+        }
+    };
+
+    let description = parse(&data)?;
+
+    if toml {
+        print!("{}", toml::to_string_pretty(&description).unwrap());
+    } else {
+        println!("{}", serde_json::to_string_pretty(&description).unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn npdm(command: NpdmCommand) -> Result<()> {
+    match command {
+        NpdmCommand::Generate {
+            description,
+            title_id,
+            out_path,
+        } => {
+            let title_id = title_id.or_else(|| {
+                crate::cargo_info::get_metadata()
+                    .ok()
+                    .and_then(|metadata| metadata.title_id)
+            });
+
+            fs::write(
+                &out_path,
+                build_from_description(&description, title_id.as_deref())?,
+            )?;
+
+            println!("Wrote NPDM to '{}'", out_path.display());
+
+            Ok(())
+        }
+        NpdmCommand::Dump { npdm, toml } => dump(npdm, toml),
+    }
+}
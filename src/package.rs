@@ -1,52 +1,331 @@
 use crate::build;
-use crate::cargo_info;
+use crate::cache;
+use crate::cargo_info::{self, PackageResource};
 use crate::error::{Error, Result};
-use crate::game_paths::{get_npdm_path, get_plugin_nro_path, get_subsdk_path};
+use crate::lock::LockFile;
+use crate::game_paths::{ATMOSPHERE_CONTENTS_DIR, get_npdm_path, get_plugin_nro_path, get_subsdk_path};
 use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
 use walkdir::WalkDir;
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{Cursor, Read, Write};
-use std::path::Path;
+use std::path::{Component, Path};
 use std::path::PathBuf;
-use std::result::Result as StdResult;
-use zip::{ZipArchive, ZipWriter};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+mod verify;
+
+#[derive(StructOpt)]
+pub enum PackageCommand {
+    #[structopt(about = "Check a packaged zip for missing or misplaced files before publishing it")]
+    Verify {
+        #[structopt(about = "Path of the zip to verify")]
+        zip: PathBuf,
+    },
+}
+
+pub fn package_command(command: PackageCommand) -> Result<()> {
+    match command {
+        PackageCommand::Verify { zip } => verify::verify(&zip),
+    }
+}
 
 pub struct Exefs {
-    pub main_npdm: Vec<u8>,
     pub subsdk1: Vec<u8>,
 }
 
-// TODO: Cache exefs to disk, figure out some strategy for cache invalidation?
-pub fn get_exefs(url: &str) -> Result<Exefs> {
-    let zip_reader = Cursor::new(
-        reqwest::blocking::get(url)
-            .map_err(|_| Error::DownloadError)?
-            .bytes()
-            .map_err(|_| Error::DownloadError)?,
-    );
+/// Expand `package-resources` into every local file along with its path relative to the root of
+/// the SD card, walking any directories
+pub fn resource_files(resources: &[PackageResource]) -> Vec<(PathBuf, PathBuf)> {
+    let mut files = Vec::new();
+
+    for resource in resources {
+        let local_path = &resource.local_path;
+        let output_path = &resource.package_path;
+
+        if Path::new(&local_path).is_dir() {
+            // Get all files in the directory and subdirectories
+            let paths = WalkDir::new(local_path)
+                .into_iter()
+                .flatten()
+                .filter(|entry| entry.file_type().is_file())
+                .map(|entry| entry.path().to_owned());
+
+            for path in paths {
+                // Strip the local directory from the path we're processing and add the destination directory as prefix
+                let package_path = output_path.join(path.strip_prefix(local_path).unwrap());
+                files.push((path, package_path));
+            }
+        } else {
+            files.push((local_path.to_owned(), output_path.to_owned()));
+        }
+    }
+
+    files
+}
+
+pub fn exefs_from_zip(data: Vec<u8>) -> Result<Exefs> {
+    let zip_reader = Cursor::new(data);
 
     let mut zip = ZipArchive::new(zip_reader).unwrap();
 
-    let subsdk1 = zip
-        .by_name("exefs/subsdk9")?
-        .bytes()
-        .collect::<StdResult<_, _>>()?;
-    let main_npdm = zip
-        .by_name("exefs/main.npdm")?
-        .bytes()
-        .collect::<StdResult<_, _>>()?;
+    let mut subsdk1 = Vec::new();
+    zip.by_name("exefs/subsdk9")?.read_to_end(&mut subsdk1)?;
+
+    Ok(Exefs { subsdk1 })
+}
+
+/// Path of the manifest within a packaged zip
+pub(crate) const MANIFEST_PATH: &str = "skyline-manifest.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct ManifestSkyline {
+    pub url: String,
+    pub version: Option<String>,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Describes the contents of a packaged zip, embedded in the zip itself
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub title_ids: Vec<String>,
+    #[serde(default)]
+    pub layout: String,
+    /// File name the plugin is installed as in each title's plugin folder, unless it's a subsdk
+    #[serde(default)]
+    pub plugin_nro: Option<String>,
+    #[serde(default)]
+    pub subsdk_name: Option<String>,
+    pub skyline: Option<ManifestSkyline>,
+    pub files: Vec<ManifestFile>,
+}
+
+fn write_zip(out_path: &str, files: &BTreeMap<String, Vec<u8>>, reproducible: bool) -> Result<()> {
+    // Fix everything which would otherwise depend on when or where the zip was built
+    let options = if reproducible {
+        FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default())
+            .unix_permissions(0o644)
+    } else {
+        FileOptions::default()
+    };
+
+    let mut zip = ZipWriter::new(fs::File::create(out_path)?);
+
+    for (path, data) in files {
+        zip.start_file(path, options)?;
+        zip.write_all(data)?;
+    }
+
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Where files are placed within a package
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// `atmosphere/contents/<tid>/...`, extracted to the root of the SD card
+    Atmosphere,
+    /// `<tid>/<mod name>/exefs|romfs`, as used by the mod folders of Ryujinx and yuzu
+    Emulator,
+    /// `exefs|romfs` of a single title, for mod managers
+    Flat,
+}
+
+impl Layout {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Layout::Atmosphere => "atmosphere",
+            Layout::Emulator => "emulator",
+            Layout::Flat => "flat",
+        }
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "atmosphere" | "" => Ok(Layout::Atmosphere),
+            "emulator" => Ok(Layout::Emulator),
+            "flat" => Ok(Layout::Flat),
+            _ => Err(format!("unknown layout '{}'", s)),
+        }
+    }
+}
+
+/// Move a file from where it goes on the SD card to where the layout puts it. Files outside of a
+/// title's contents are kept under `sdmc/` as they can't be installed as part of a mod.
+pub(crate) fn layout_path(layout: Layout, path: &str, mod_name: &str) -> String {
+    let contents_dir = format!("{}/", &ATMOSPHERE_CONTENTS_DIR[1..]);
+
+    match (layout, path.strip_prefix(&contents_dir)) {
+        (Layout::Atmosphere, _) => path.to_owned(),
+        (Layout::Emulator, Some(title_path)) => match title_path.split_once('/') {
+            Some((title_id, rest)) => format!("{}/{}/{}", title_id, mod_name, rest),
+            None => title_path.to_owned(),
+        },
+        (Layout::Flat, Some(title_path)) => match title_path.split_once('/') {
+            Some((_, rest)) => rest.to_owned(),
+            None => title_path.to_owned(),
+        },
+        (_, None) => format!("sdmc/{}", path),
+    }
+}
+
+/// Delete the files of a previous package listed in its manifest, along with any directories left
+/// empty, so that anything else in the directory is kept
+fn remove_previous_package(out_dir: &Path) -> Result<()> {
+    let manifest: Manifest = match fs::read(out_dir.join(MANIFEST_PATH))
+        .ok()
+        .and_then(|manifest| serde_json::from_slice(&manifest).ok())
+    {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+
+    for file in &manifest.files {
+        let path = Path::new(&file.path);
+
+        // Never follow a manifest out of the package directory
+        if !path.components().all(|part| matches!(part, Component::Normal(_))) {
+            continue;
+        }
+
+        let path = out_dir.join(path);
+        if path.is_file() {
+            fs::remove_file(&path)?;
+        }
+
+        for dir in path.ancestors().skip(1).take_while(|dir| *dir != out_dir) {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the package out as a directory rather than a zip, replacing a previous package there
+fn write_directory(out_path: &str, files: &BTreeMap<String, Vec<u8>>) -> Result<()> {
+    let out_dir = Path::new(out_path);
+
+    remove_previous_package(out_dir)?;
+
+    for (path, data) in files {
+        let path = out_dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, data)?;
+    }
+
+    Ok(())
+}
 
-    Ok(Exefs { main_npdm, subsdk1 })
+/// The target directory passed to cargo with `--target-dir`, if any
+fn target_dir_arg(args: &[String]) -> Option<PathBuf> {
+    args.iter().enumerate().find_map(|(i, arg)| match arg.strip_prefix("--target-dir") {
+        Some("") => args.get(i + 1).map(PathBuf::from),
+        Some(rest) => rest.strip_prefix('=').map(PathBuf::from),
+        None => None,
+    })
 }
 
+/// Name the zip after the profile and feature set so that variants don't overwrite each other
+fn default_out_path(
+    target_dir: &Path,
+    profile: &str,
+    features: &[String],
+    no_default_features: bool,
+) -> String {
+    let mut name = profile.to_owned();
+
+    if no_default_features {
+        name.push_str("-no-default-features");
+    }
+
+    // The same features in any order are the same variant
+    let mut features: Vec<_> = features
+        .iter()
+        .flat_map(|features| features.split([',', ' ']))
+        .filter(|feature| !feature.is_empty())
+        .collect();
+    features.sort_unstable();
+    features.dedup();
+
+    // Features of dependencies (`dep/feat`) would otherwise add a directory, so anything which
+    // isn't safe in a file name is replaced
+    for feature in features {
+        name.push('+');
+        name.extend(feature.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        }));
+    }
+
+    target_dir
+        .join(format!("{}.zip", name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Insert the title ID before the extension of the zip when splitting it per title
+fn split_out_path(out_path: &str, title_id: &str) -> String {
+    match out_path.strip_suffix(".zip") {
+        Some(stem) => format!("{}-{}.zip", stem, title_id),
+        None => format!("{}-{}", out_path, title_id),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn package(
-    skyline_url: &str,
-    title_id: Option<&str>,
-    out_path: &str,
+    skyline_url: Option<&str>,
+    title_ids: Vec<String>,
+    out_path: Option<String>,
     include_skyline: bool,
     subsdk: bool,
+    release: bool,
+    profile: &str,
+    features: Vec<String>,
+    no_default_features: bool,
+    update_lock: bool,
+    reproducible: bool,
+    split: bool,
+    layout: Layout,
+    directory: bool,
+    mut args: Vec<String>,
 ) -> Result<()> {
-    let args = vec![String::from("--release")];
+    let target_dir = target_dir_arg(&args);
+
+    if release {
+        args.push(String::from("--release"));
+    }
+
+    if !features.is_empty() {
+        args.push(format!("--features={}", features.join(",")));
+    }
+
+    if no_default_features {
+        args.push("--no-default-features".to_owned());
+    }
 
     let binary_path = if subsdk {
         build::build_get_nso(args)?
@@ -59,101 +338,190 @@ pub fn package(
 
     let metadata = cargo_info::get_metadata()?;
 
-    let title_id = title_id
-        .or(metadata.title_id.as_deref())
-        .ok_or(Error::NoTitleId)?;
+    let out_path = out_path.unwrap_or_else(|| {
+        let target_dir = target_dir.as_deref().unwrap_or(&metadata.target_dir);
+
+        default_out_path(target_dir, profile, &features, no_default_features)
+    });
+
+    let out_path = match out_path.strip_suffix(".zip") {
+        Some(out_dir) if directory => out_dir.to_owned(),
+        _ => out_path,
+    };
+
+    let title_ids = if title_ids.is_empty() {
+        metadata.title_ids.clone()
+    } else {
+        title_ids
+    };
 
+    if title_ids.is_empty() {
+        return Err(Error::NoTitleId);
+    }
+
+    if layout == Layout::Flat && title_ids.len() > 1 && !split {
+        return Err(Error::FlatLayoutMultipleTitles);
+    }
+
+    let mut skyline = None;
     let exefs = if include_skyline && !subsdk {
         println!("Downloading latest Skyline release...");
-        Some(get_exefs(skyline_url)?)
+        let release = &metadata.skyline_release;
+        let mut lock = LockFile::load(&metadata.workspace_root, update_lock)?;
+        let url = lock.skyline_url(skyline_url.unwrap_or(&release.url()))?;
+
+        let data = cache::download(&url)?;
+
+        lock.verify_skyline(
+            &url,
+            release.version.as_deref(),
+            release.sha256.as_deref(),
+            &data,
+        )?;
+        lock.save()?;
+
+        skyline = Some(ManifestSkyline {
+            version: release.version.clone(),
+            sha256: cache::sha256_hex(&data),
+            url,
+        });
+
+        Some(exefs_from_zip(data)?)
     } else {
         None
     };
 
-    println!("Building Zip File...");
     let binary_data = fs::read(&binary_path)?;
 
-    let mut zip = ZipWriter::new(fs::File::create(out_path)?);
+    if metadata.npdm_path.is_none() && exefs.is_some() {
+        eprintln!("\n{}: defaulting to a generated NPDM.", "Warning".yellow());
+        eprintln!(
+            "{}: To specify a custom npdm add the following to your Cargo.toml:",
+            "NOTE".bright_blue()
+        );
+        eprintln!("\n{}\n", "[package.metadata.skyline]".bright_blue());
+        eprintln!("{}\n", "custom-npdm = \"path/to/your.npdm\" # or npdm.json/npdm.toml".bright_blue());
+    }
 
-    let binary_install_path = if subsdk {
-        get_subsdk_path(title_id, metadata.subsdk_name.as_deref().unwrap_or("subsdk9"))[1..].to_string()
+    let mut resources = BTreeMap::new();
+    for (local_path, output_path) in resource_files(&metadata.package_resources) {
+        let data = std::fs::read(&local_path).map_err(|_| Error::PackageResourceMissing(local_path))?;
+        resources.insert(output_path.to_str().unwrap().to_owned(), data);
+    }
+
+    let subsdk_name = metadata.subsdk_name.as_deref().unwrap_or("subsdk9");
+
+    let title_files = |title_id: &str| -> Result<_> {
+        let mut files = BTreeMap::new();
+
+        let binary_install_path = if subsdk {
+            get_subsdk_path(title_id, subsdk_name)[1..].to_string()
+        } else {
+            get_plugin_nro_path(title_id, plugin_name.as_ref())[1..].to_string()
+        };
+
+        files.insert(binary_install_path, binary_data.clone());
+
+        // Assuming we are building a subsdk, there are few reasons to also want Skyline
+        if let Some(exefs) = &exefs {
+            files.insert(
+                get_subsdk_path(title_id, subsdk_name)[1..].to_string(),
+                exefs.subsdk1.clone(),
+            );
+        }
+
+        // We could want a custom NPDM instead of Skyline's, so write it here
+        let npdm = match crate::npdm::custom_npdm(&metadata, title_id)? {
+            Some(npdm) => Some(npdm),
+            None if exefs.is_some() => Some(crate::installer::generate_npdm(title_id)),
+            None => None,
+        };
+
+        if let Some(npdm) = npdm {
+            files.insert(get_npdm_path(title_id)[1..].to_string(), npdm);
+        }
+
+        Ok(files)
+    };
+
+    let zips = if split {
+        title_ids
+            .iter()
+            .map(|title_id| (split_out_path(&out_path, title_id), vec![title_id.clone()]))
+            .collect()
     } else {
-        get_plugin_nro_path(title_id, plugin_name.as_ref())[1..].to_string()
+        vec![(out_path, title_ids)]
     };
 
-    zip.start_file(
-        binary_install_path,
-        Default::default(),
-    )?;
-
-    zip.write_all(&binary_data)?;
-
-    // main.npdm
-    let main_npdm = metadata
-        .npdm_path
-        .as_ref()
-        .map(fs::read)
-        .transpose()
-        .map_err(|_| Error::NoNpdmFileFound)?;
-
-    // Assuming we are building a subsdk, there are few reasons to also want Skyline
-    if include_skyline && !subsdk {
-        let generated_npdm = crate::installer::generate_npdm(title_id);
-        
-        main_npdm.as_ref().unwrap_or_else(|| {
-            eprintln!("\n{}: defaulting to a generated NPDM.", "Warning".yellow());
+    for (out_path, title_ids) in zips {
+        println!("Building {}...", if directory { "Directory" } else { "Zip File" });
+
+        // Entries are kept sorted so the same inputs always produce the same zip
+        let mut files = resources.clone();
+        for title_id in &title_ids {
+            files.extend(title_files(title_id)?);
+        }
+
+        let mut files: BTreeMap<_, _> = files
+            .into_iter()
+            .map(|(path, data)| (layout_path(layout, &path, &metadata.name), data))
+            .collect();
+
+        if layout != Layout::Atmosphere && files.keys().any(|path| path.starts_with("sdmc/")) {
             eprintln!(
-                "{}: To specify a custom npdm add the following to your Cargo.toml:",
+                "{}: files outside of the game's contents have been placed in 'sdmc/', these need to be copied to the SD card separately",
                 "NOTE".bright_blue()
             );
-            eprintln!("\n{}\n", "[package.metadata.skyline]".bright_blue());
-            eprintln!("{}\n", "custom-npdm = \"path/to/your.npdm\"".bright_blue());
-            &generated_npdm
-        });
+        }
 
-        // subsdk
-        let subsdk_name = metadata.subsdk_name.as_deref().unwrap_or("subsdk9");
-        zip.start_file(
-            get_subsdk_path(title_id, subsdk_name)[1..].to_string(),
-            Default::default(),
-        )?;
-        zip.write_all(&exefs.unwrap().subsdk1)?;
-    }
+        let manifest = Manifest {
+            name: metadata.name.clone(),
+            version: metadata.version.clone(),
+            title_ids,
+            layout: layout.to_str().to_owned(),
+            plugin_nro: (!subsdk).then(|| plugin_name.to_string()),
+            subsdk_name: Some(subsdk_name.to_owned()),
+            skyline: skyline.clone(),
+            files: files
+                .iter()
+                .map(|(path, data)| ManifestFile {
+                    path: path.clone(),
+                    size: data.len() as u64,
+                    sha256: cache::sha256_hex(data),
+                })
+                .collect(),
+        };
 
-    // We could want a custom NPDM instead of Skyline's, so write it here
-    if let Some(main_npdm) = main_npdm {
-        zip.start_file(get_npdm_path(title_id)[1..].to_string(), Default::default())?;
-        zip.write_all(&main_npdm)?;
-    }
+        files.insert(
+            MANIFEST_PATH.to_owned(),
+            serde_json::to_vec_pretty(&manifest).unwrap(),
+        );
 
-    for resource in &metadata.package_resources {
-        let local_path = &resource.local_path;
-        let output_path = &resource.package_path;
+        if directory {
+            write_directory(&out_path, &files)?;
 
-        if Path::new(&local_path).is_dir() {
-            // Get all files in the directory and subdirectories
-            let paths: Vec<PathBuf> = WalkDir::new(&local_path).into_iter().flatten().filter(|entry| entry.file_type().is_file()).map(|entry| entry.path().to_owned()).collect();
+            println!("Finished building directory at '{}'", out_path);
 
-            for path in paths {
-                // Strip the local directory from the path we're processing and add the destination directory as prefix
-                zip.start_file(
-                    output_path.join(&path.strip_prefix(&local_path).unwrap()).to_str().unwrap(),
-                    Default::default(),
-                )?;
-    
-                zip.write_all(&std::fs::read(&path).map_err(|_| Error::PackageResourceMissing(path))?)?;
-            }
-        } else {
-            zip.start_file(
-                output_path.to_str().unwrap(),
-                Default::default(),
+            continue;
+        }
+
+        write_zip(&out_path, &files, reproducible)?;
+
+        println!("Finished building zip at '{}'", out_path);
+
+        // Anything built for release gets a checksum, including with custom profiles
+        if !matches!(profile, "dev" | "debug") {
+            let file_name = Path::new(&out_path).file_name().unwrap().to_string_lossy();
+            let sha256 = cache::sha256_hex(&fs::read(&out_path)?);
+
+            fs::write(
+                format!("{}.sha256", out_path),
+                format!("{}  {}\n", sha256, file_name),
             )?;
 
-            zip.write_all(&std::fs::read(&local_path).map_err(|_| Error::PackageResourceMissing(local_path.to_owned()))?)?;
+            println!("sha256: {}", sha256);
         }
     }
 
-    println!("Finished building zip at '{}'", out_path);
-
     Ok(())
 }
@@ -0,0 +1,222 @@
+use super::{layout_path, resource_files, Layout, Manifest, MANIFEST_PATH};
+use crate::cache::sha256_hex;
+use crate::cargo_info;
+use crate::error::{Error, Result};
+use crate::game_paths::{get_game_path, get_npdm_path, get_plugin_nro_path, get_subsdk_path};
+use crate::inspect;
+use owo_colors::OwoColorize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Read the title ID an NPDM was built for
+fn npdm_title_id(npdm: &[u8]) -> Option<u64> {
+    crate::npdm::parse(npdm).ok().map(|npdm| npdm.title_id.0)
+}
+
+#[derive(Default)]
+struct Report {
+    errors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn warn(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+}
+
+fn read_zip(path: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut zip = ZipArchive::new(Cursor::new(fs::read(path)?))?;
+    let mut files = BTreeMap::new();
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        files.insert(file.name().to_owned(), data);
+    }
+
+    Ok(files)
+}
+
+fn check_manifest(files: &BTreeMap<String, Vec<u8>>, manifest: &Manifest, report: &mut Report) {
+    for file in &manifest.files {
+        match files.get(&file.path) {
+            Some(data) if sha256_hex(data) != file.sha256 => {
+                report.error(format!("'{}' does not match the hash in the manifest", file.path))
+            }
+            Some(_) => (),
+            None => report.error(format!("'{}' is listed in the manifest but missing", file.path)),
+        }
+    }
+}
+
+fn check_binary(
+    files: &BTreeMap<String, Vec<u8>>,
+    path: &str,
+    format: &str,
+    report: &mut Report,
+) {
+    match files.get(path) {
+        Some(data) => match inspect::parse_binary_data(path.into(), data) {
+            Ok(info) if info.format == format => (),
+            Ok(info) => report.error(format!(
+                "'{}' is an {} rather than an {}",
+                path, info.format, format
+            )),
+            Err(_) => report.error(format!("'{}' could not be parsed as an {}", path, format)),
+        },
+        None => report.error(format!("'{}' is missing", path)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_title(
+    files: &BTreeMap<String, Vec<u8>>,
+    title_id: &str,
+    layout: Layout,
+    name: &str,
+    nro_name: &str,
+    subsdk_name: &str,
+    has_skyline: bool,
+    report: &mut Report,
+) {
+    let path = |path: String| layout_path(layout, &path[1..], name);
+
+    let nro_path = path(get_plugin_nro_path(title_id, nro_name));
+    let subsdk_path = path(get_subsdk_path(title_id, subsdk_name));
+
+    // Without Skyline the plugin may itself be the subsdk
+    if has_skyline || !files.contains_key(&subsdk_path) {
+        check_binary(files, &nro_path, "NRO", report);
+    }
+
+    if has_skyline || !files.contains_key(&nro_path) {
+        check_binary(files, &subsdk_path, "NSO", report);
+    }
+
+    // Anything else in exefs would be loaded by the game, so is most likely misnamed
+    let exefs = path(get_game_path(title_id) + "/exefs/");
+    let npdm_path = path(get_npdm_path(title_id));
+    for file in files.keys().filter(|file| file.starts_with(&exefs)) {
+        if *file != npdm_path && *file != subsdk_path {
+            report.error(format!("unexpected file '{}' in exefs, expected '{}'", file, subsdk_path));
+        }
+    }
+
+    match files.get(&npdm_path) {
+        Some(npdm) => match npdm_title_id(npdm) {
+            Some(npdm_title_id) if format!("{:016X}", npdm_title_id) == title_id.to_uppercase() => (),
+            Some(npdm_title_id) => report.error(format!(
+                "'{}' is for title ID {:016X} rather than {}",
+                npdm_path, npdm_title_id, title_id
+            )),
+            None => report.error(format!("'{}' is not a valid NPDM", npdm_path)),
+        },
+        None if has_skyline => report.error(format!(
+            "'{}' is missing, Skyline will not be loaded without it",
+            npdm_path
+        )),
+        None => (),
+    }
+}
+
+pub fn verify(zip_path: &Path) -> Result<()> {
+    let files = read_zip(zip_path)?;
+
+    let manifest: Option<Manifest> = files
+        .get(MANIFEST_PATH)
+        .and_then(|manifest| serde_json::from_slice(manifest).ok());
+
+    // Outside of a plugin everything is taken from the manifest
+    let metadata = cargo_info::get_metadata().ok();
+
+    let mut report = Report::default();
+
+    let (title_ids, name) = match (&manifest, &metadata) {
+        (Some(manifest), _) => (manifest.title_ids.clone(), manifest.name.clone()),
+        (None, Some(metadata)) => (metadata.title_ids.clone(), metadata.name.clone()),
+        (None, None) => (vec![], String::new()),
+    };
+
+    if title_ids.is_empty() {
+        return Err(Error::NoTitleId);
+    }
+
+    let layout: Layout = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.layout.parse().ok())
+        .unwrap_or(Layout::Atmosphere);
+
+    match &manifest {
+        Some(manifest) => check_manifest(&files, manifest, &mut report),
+        None => report.warn(format!("'{}' is missing, file hashes can't be checked", MANIFEST_PATH)),
+    }
+
+    // Zips packaged before the manifest recorded these are assumed to use the defaults
+    let nro_name = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.plugin_nro.clone())
+        .unwrap_or_else(|| format!("lib{}.nro", name.replace('-', "_")));
+    let subsdk_name = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.subsdk_name.as_deref())
+        .or_else(|| metadata.as_ref()?.subsdk_name.as_deref())
+        .unwrap_or("subsdk9");
+
+    let has_skyline = match &manifest {
+        Some(manifest) => manifest.skyline.is_some(),
+        None => files.keys().any(|file| file.ends_with(".nro"))
+            && files.keys().any(|file| file.ends_with(&format!("exefs/{}", subsdk_name))),
+    };
+
+    for title_id in &title_ids {
+        check_title(&files, title_id, layout, &name, &nro_name, subsdk_name, has_skyline, &mut report);
+    }
+
+    if let Some(metadata) = &metadata {
+        for (local_path, package_path) in resource_files(&metadata.package_resources) {
+            let path = layout_path(layout, package_path.to_str().unwrap(), &name);
+
+            match (files.get(&path), fs::read(&local_path)) {
+                (None, _) => report.error(format!(
+                    "package resource '{}' is missing from '{}'",
+                    local_path.display(),
+                    path
+                )),
+                (Some(data), Ok(local)) if *data != local => report.warn(format!(
+                    "'{}' differs from the local copy at '{}'",
+                    path,
+                    local_path.display()
+                )),
+                _ => (),
+            }
+        }
+    }
+
+    for warning in &report.warnings {
+        eprintln!("{}: {}", "WARN".yellow().bold(), warning);
+    }
+
+    for error in &report.errors {
+        eprintln!("{}: {}", "ERROR".red().bold(), error);
+    }
+
+    if report.errors.is_empty() {
+        println!("{}", format!("'{}' verified!", zip_path.display()).green());
+        Ok(())
+    } else {
+        Err(Error::PackageVerifyFailed(report.errors.len()))
+    }
+}
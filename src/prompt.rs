@@ -0,0 +1,51 @@
+use crate::error::{Error, Result};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_assume_yes() {
+    ASSUME_YES.store(true, Ordering::Relaxed);
+}
+
+pub fn set_non_interactive() {
+    NON_INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|value| !value.is_empty() && value != "0" && value != "false")
+        .unwrap_or(false)
+}
+
+/// Whether the user can be asked questions, which isn't the case with `--non-interactive`, on CI
+/// or without a terminal to ask them in
+pub fn is_interactive() -> bool {
+    !NON_INTERACTIVE.load(Ordering::Relaxed)
+        && !env_flag("CI")
+        && !env_flag("CARGO_SKYLINE_NONINTERACTIVE")
+        && std::io::stdin().is_terminal()
+}
+
+/// Whether progress bars can be drawn, otherwise progress should be printed as plain text
+pub fn is_tty() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Ask the user to confirm something, accepting it with `--yes` and failing when non-interactive
+pub fn confirm(prompt: &str) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+
+    if !is_interactive() {
+        return Err(Error::NeedsConfirmation(prompt.to_owned()));
+    }
+
+    dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(true)
+        .interact()
+        .map_err(|_| Error::NeedsConfirmation(prompt.to_owned()))
+}
@@ -0,0 +1,203 @@
+use crate::build;
+use crate::error::{Error, Result};
+use goblin::elf::section_header::{SHF_ALLOC, SHT_NOBITS};
+use goblin::elf::sym::STT_FUNC;
+use goblin::elf::Elf;
+use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Default)]
+struct SizeReport {
+    total: u64,
+    /// Sections such as `.bss` which take up memory once loaded but no space in the binary
+    #[serde(default)]
+    uninitialized: u64,
+    sections: BTreeMap<String, u64>,
+    crates: BTreeMap<String, u64>,
+    #[serde(default)]
+    functions: BTreeMap<String, u64>,
+}
+
+pub(crate) fn format_size(size: u64) -> String {
+    match size {
+        0..=1023 => format!("{} B", size),
+        1024..=0xFFFFF => format!("{:.1} KiB", size as f64 / 1024.0),
+        0x100000..=0x3FFFFFFF => format!("{:.2} MiB", size as f64 / (1024.0 * 1024.0)),
+        _ => format!("{:.2} GiB", size as f64 / (1024.0 * 1024.0 * 1024.0)),
+    }
+}
+
+fn format_diff(new: u64, old: Option<u64>) -> String {
+    match old {
+        Some(old) if new > old => format!("+{}", format_size(new - old)).red().to_string(),
+        Some(old) if new < old => format!("-{}", format_size(old - new)).green().to_string(),
+        Some(_) => String::new(),
+        None => "new".yellow().to_string(),
+    }
+}
+
+/// Guess which crate a symbol came from using the first path segment of its demangled name
+fn crate_name(demangled: &str) -> String {
+    let path = demangled.trim_start_matches('<');
+
+    match path.split_once("::") {
+        Some((krate, _)) if !krate.contains(' ') => krate.to_owned(),
+        _ => String::from("[unknown]"),
+    }
+}
+
+/// The path the report of the previous build of the given artifact is stored at
+fn report_path(artifact: &Path) -> PathBuf {
+    artifact.with_extension("size.json")
+}
+
+fn analyze(data: &[u8]) -> Result<SizeReport> {
+    let elf = Elf::parse(data).map_err(|_| Error::BadBinaryFormat)?;
+
+    let mut report = SizeReport::default();
+
+    for shdr in &elf.section_headers {
+        if shdr.sh_flags & SHF_ALLOC as u64 == 0 || shdr.sh_size == 0 {
+            continue;
+        }
+
+        let name = elf.shdr_strtab.get_at(shdr.sh_name).unwrap_or_default();
+        *report.sections.entry(name.to_owned()).or_default() += shdr.sh_size;
+
+        if shdr.sh_type == SHT_NOBITS {
+            report.uninitialized += shdr.sh_size;
+        } else {
+            report.total += shdr.sh_size;
+        }
+    }
+
+    for sym in elf.syms.iter() {
+        if sym.st_type() != STT_FUNC || sym.st_size == 0 {
+            continue;
+        }
+
+        let name = elf.strtab.get_at(sym.st_name).unwrap_or_default();
+        let demangled = format!("{:#}", rustc_demangle::demangle(name));
+
+        *report.crates.entry(crate_name(&demangled)).or_default() += sym.st_size;
+        *report.functions.entry(demangled).or_default() += sym.st_size;
+    }
+
+    Ok(report)
+}
+
+/// The largest entries of a table of sizes
+fn largest(table: &BTreeMap<String, u64>, count: usize) -> Vec<(&String, &u64)> {
+    let mut entries: Vec<_> = table.iter().collect();
+    entries.sort_by_key(|(_, size)| Reverse(**size));
+    entries.truncate(count);
+
+    entries
+}
+
+pub fn size(mut args: Vec<String>, release: bool, top: usize) -> Result<()> {
+    if release {
+        args.push("--release".into());
+    }
+
+    let artifact = build::build_get_artifact(args)?;
+    let report = analyze(&fs::read(&artifact)?)?;
+
+    let previous: Option<SizeReport> = fs::read(report_path(&artifact))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok());
+
+    let old_size = |table: fn(&SizeReport) -> &BTreeMap<String, u64>, name: &str| {
+        previous
+            .as_ref()
+            .map(|previous| table(previous).get(name).copied().unwrap_or(0))
+    };
+
+    println!(
+        "{}: {} {}",
+        "Total".bright_blue(),
+        format_size(report.total),
+        format_diff(report.total, previous.as_ref().map(|previous| previous.total))
+    );
+
+    if report.uninitialized != 0 {
+        println!(
+            "{}: {} {}",
+            "Uninitialized".bright_blue(),
+            format_size(report.uninitialized),
+            format_diff(
+                report.uninitialized,
+                previous.as_ref().map(|previous| previous.uninitialized)
+            )
+        );
+    }
+
+    println!("\n{}:", "Sections".bright_blue());
+    for (name, &size) in &report.sections {
+        println!(
+            "  {:<20} {:>12} {}",
+            name,
+            format_size(size),
+            format_diff(size, old_size(|report| &report.sections, name))
+        );
+    }
+
+    println!("\n{}:", "Top crates".bright_blue());
+    for (name, &size) in largest(&report.crates, top) {
+        println!(
+            "  {:<30} {:>12} {}",
+            name,
+            format_size(size),
+            format_diff(size, old_size(|report| &report.crates, name))
+        );
+    }
+
+    println!("\n{}:", "Top functions".bright_blue());
+    for (name, &size) in largest(&report.functions, top) {
+        println!(
+            "  {:>12}  {} {}",
+            format_size(size),
+            name,
+            format_diff(size, old_size(|report| &report.functions, name))
+        );
+    }
+
+    if let Some(previous) = &previous {
+        let removed: Vec<_> = previous
+            .crates
+            .keys()
+            .filter(|name| !report.crates.contains_key(*name))
+            .collect();
+
+        if !removed.is_empty() {
+            println!("\n{}:", "Removed crates".bright_blue());
+            for name in removed {
+                println!("  {}", name);
+            }
+        }
+
+        // Only the previously largest functions, as any change can remove thousands of small ones
+        let removed: Vec<_> = largest(&previous.functions, top)
+            .into_iter()
+            .filter(|(name, _)| !report.functions.contains_key(*name))
+            .collect();
+
+        if !removed.is_empty() {
+            println!("\n{}:", "Removed functions".bright_blue());
+            for (name, &size) in removed {
+                println!("  {:>12}  {}", format_size(size), name);
+            }
+        }
+    }
+
+    fs::write(
+        report_path(&artifact),
+        serde_json::to_string_pretty(&report).unwrap(),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,472 @@
+use crate::build::get_rustup_home;
+use crate::cargo_info;
+use crate::error::{Error, Result};
+use crate::size::format_size;
+use crate::update_std::{self, get_cargo_skyline_dir, PathExt, SourceArgs};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use owo_colors::OwoColorize;
+use std::collections::HashSet;
+use std::fs;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use structopt::StructOpt;
+use walkdir::WalkDir;
+
+mod verify;
+
+pub use verify::{is_complete, mark_complete, mark_incomplete};
+
+/// The toolchain used when neither a default nor a pin has been set
+pub const DEFAULT_TOOLCHAIN: &str = "skyline-v3";
+
+/// The directory `DEFAULT_TOOLCHAIN` is installed to, which can't be used as a toolchain name
+const DEFAULT_TOOLCHAIN_DIR: &str = "skyline";
+
+const INFO_FILE: &str = "skyline-toolchain.json";
+
+/// How a toolchain was installed, stored inside the toolchain so that it can be reproduced
+#[derive(Serialize, Deserialize)]
+pub struct ToolchainInfo {
+    pub repo: String,
+    pub git_ref_kind: String,
+    pub git_ref: String,
+    pub commit: String,
+    pub base_nightly: String,
+    pub mirror: Option<String>,
+    pub cargo_skyline_version: String,
+}
+
+impl ToolchainInfo {
+    pub fn read(toolchain: &Path) -> Option<Self> {
+        serde_json::from_slice(&fs::read(toolchain.join(INFO_FILE)).ok()?).ok()
+    }
+
+    pub fn write(&self, toolchain: &Path) -> Result<()> {
+        fs::write(
+            toolchain.join(INFO_FILE),
+            serde_json::to_string_pretty(self).unwrap(),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+pub enum ToolchainCommand {
+    #[structopt(about = "List the installed skyline toolchains")]
+    List,
+    #[structopt(about = "Install a skyline toolchain under the given name")]
+    Install {
+        #[structopt(about = "Name to install the toolchain as, such as `skyline-2024-03`")]
+        name: String,
+
+        #[structopt(flatten)]
+        source: SourceArgs,
+
+        #[structopt(
+            long,
+            about = "Rather than shallow clone, perform a deep clone, allowing changes to be pushed afterwards"
+        )]
+        deep: bool,
+
+        #[structopt(long, about = "Make the toolchain the default once installed")]
+        default: bool,
+    },
+    #[structopt(about = "Remove an installed skyline toolchain")]
+    Remove { name: String },
+    #[structopt(about = "Show or set the toolchain used by projects without a `toolchain` pin")]
+    Default { name: Option<String> },
+    #[structopt(about = "Show how a toolchain was installed and how to reproduce it")]
+    Info {
+        #[structopt(about = "Toolchain to show, defaults to the one used by the current project")]
+        name: Option<String>,
+    },
+    #[structopt(about = "Check that a toolchain is completely installed and runs")]
+    Verify {
+        #[structopt(about = "Toolchain to check, defaults to the one used by the current project")]
+        name: Option<String>,
+    },
+    #[structopt(about = "Fix the broken parts of a toolchain without reinstalling all of it")]
+    Repair {
+        #[structopt(about = "Toolchain to repair, defaults to the one used by the current project")]
+        name: Option<String>,
+    },
+    #[structopt(
+        about = "Bundle a toolchain and its rust-src into a tarball for `update-std --from-archive`"
+    )]
+    Export {
+        #[structopt(about = "Toolchain to export, defaults to the one used by the current project")]
+        name: Option<String>,
+
+        #[structopt(
+            short,
+            long,
+            parse(from_os_str),
+            about = "Path to write the archive to, defaults to `<name>.tar.gz`"
+        )]
+        out_path: Option<PathBuf>,
+    },
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if !is_valid {
+        Err(Error::InvalidToolchainName(name.to_owned()))
+    } else if name == DEFAULT_TOOLCHAIN_DIR {
+        Err(Error::ReservedToolchainName(name.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn toolchains_dir() -> PathBuf {
+    get_cargo_skyline_dir().push_join("toolchain").ensure_exists()
+}
+
+fn default_toolchain_path() -> PathBuf {
+    get_cargo_skyline_dir().push_join("default-toolchain")
+}
+
+/// The directory the given toolchain is installed to. `skyline-v3` keeps the directory it was
+/// installed to before toolchains were named.
+pub fn toolchain_dir(name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
+
+    let dir_name = if name == DEFAULT_TOOLCHAIN { DEFAULT_TOOLCHAIN_DIR } else { name };
+
+    Ok(toolchains_dir().push_join(dir_name))
+}
+
+/// Whether rustup knows about the given toolchain
+pub fn is_linked(name: &str) -> Result<bool> {
+    Ok(get_rustup_home()?.push_join("toolchains").push_join(name).exists())
+}
+
+/// Whether the given rustup toolchain is a link to a toolchain installed by cargo-skyline, as
+/// opposed to one installed through rustup itself
+fn links_to_skyline(name: &str) -> Result<bool> {
+    let link = get_rustup_home()?.push_join("toolchains").push_join(name);
+    let dir = toolchains_dir();
+
+    // rustup may have canonicalized the path when linking
+    Ok(fs::read_link(link)
+        .map(|target| {
+            target.starts_with(&dir)
+                || dir.canonicalize().is_ok_and(|dir| target.starts_with(dir))
+        })
+        .unwrap_or(false))
+}
+
+/// Whether the given toolchain was installed by cargo-skyline, either still being in the
+/// toolchain directory or left behind as a link to it
+fn is_installed(name: &str) -> Result<bool> {
+    Ok(toolchain_dir(name)?.exists() || links_to_skyline(name)?)
+}
+
+pub fn default_toolchain() -> String {
+    fs::read_to_string(default_toolchain_path())
+        .ok()
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_TOOLCHAIN.to_owned())
+}
+
+/// The toolchain to build the current project with, either the one pinned in Cargo.toml or the
+/// default
+pub fn project_toolchain() -> String {
+    cargo_info::get_pinned_toolchain().unwrap_or_else(default_toolchain)
+}
+
+fn installed() -> Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    for entry in fs::read_dir(toolchains_dir())? {
+        let entry = entry?;
+
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        // Skip anything left over from unpacking an archive
+        if entry.file_type()?.is_dir() && !name.starts_with('.') {
+            names.push(if name == "skyline" {
+                DEFAULT_TOOLCHAIN.to_owned()
+            } else {
+                name
+            });
+        }
+    }
+
+    names.sort();
+
+    Ok(names)
+}
+
+/// How much space a toolchain takes up, along with how much of that is hard linked with other
+/// toolchains, such as the base nightly it was installed from
+#[derive(Default)]
+struct DiskUsage {
+    total: u64,
+    shared: u64,
+}
+
+/// Get the disk usage of a toolchain. Files in `seen` are left out of `unique`, so that files
+/// linked between toolchains are only counted once in the total.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn disk_usage(dir: &Path, seen: &mut HashSet<(u64, u64)>, unique: &mut DiskUsage) -> DiskUsage {
+    let mut usage = DiskUsage::default();
+
+    for entry in WalkDir::new(dir).into_iter().filter_map(|entry| entry.ok()) {
+        let metadata = match entry.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+
+        usage.total += metadata.len();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if metadata.nlink() > 1 {
+                usage.shared += metadata.len();
+            }
+
+            if !seen.insert((metadata.dev(), metadata.ino())) {
+                continue;
+            }
+
+            if metadata.nlink() > 1 {
+                unique.shared += metadata.len();
+            }
+        }
+
+        unique.total += metadata.len();
+    }
+
+    usage
+}
+
+fn list() -> Result<()> {
+    let names = installed()?;
+
+    if names.is_empty() {
+        println!("No toolchains installed, install one with `cargo skyline toolchain install <name>`");
+        return Ok(());
+    }
+
+    let default = default_toolchain();
+    let pinned = cargo_info::get_pinned_toolchain();
+    let mut seen = HashSet::new();
+    let mut total = DiskUsage::default();
+
+    for name in names {
+        let usage = disk_usage(&toolchain_dir(&name)?, &mut seen, &mut total);
+
+        print!("{} {}", name, format_usage(&usage).dimmed());
+
+        if name == default {
+            print!(" {}", "(default)".bright_blue());
+        }
+
+        if pinned.as_deref() == Some(&name) {
+            print!(" {}", "(pinned by this project)".green());
+        }
+
+        if !is_linked(&name)? {
+            print!(" {}", "(not linked, reinstall to use)".yellow());
+        }
+
+        println!();
+    }
+
+    println!("\n{} {}", "Total:".bold(), format_usage(&total));
+
+    Ok(())
+}
+
+fn format_usage(usage: &DiskUsage) -> String {
+    if usage.shared == 0 {
+        format!("({})", format_size(usage.total))
+    } else {
+        format!(
+            "({}, {} shared)",
+            format_size(usage.total),
+            format_size(usage.shared)
+        )
+    }
+}
+
+fn install(name: String, source: SourceArgs, deep: bool, default: bool) -> Result<()> {
+    validate_name(&name)?;
+
+    update_std::install_toolchain(&name, source, deep)?;
+
+    if default {
+        set_default(&name)?;
+    }
+
+    Ok(())
+}
+
+fn remove(name: String) -> Result<()> {
+    if name == default_toolchain() {
+        return Err(Error::RemoveDefaultToolchain(name));
+    }
+
+    if !is_installed(&name)? {
+        return Err(Error::ToolchainNotInstalled(name));
+    }
+
+    let dir = toolchain_dir(&name)?;
+    let linked = links_to_skyline(&name)?;
+
+    if cargo_info::get_pinned_toolchain().as_deref() == Some(&name) {
+        eprintln!(
+            "{}: '{}' is pinned by this project, it will need to be reinstalled to build it",
+            "WARN".yellow().bold(),
+            name
+        );
+    }
+
+    // Remove the toolchain before the link, so that rustup is only ever removing a dangling link
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+
+    if linked {
+        Command::new("rustup")
+            .args(["toolchain", "uninstall", &name])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|_| Error::RustupNotFound)?;
+    }
+
+    println!("Removed toolchain '{}'", name);
+
+    Ok(())
+}
+
+fn set_default(name: &str) -> Result<()> {
+    if !is_installed(name)? || !is_linked(name)? {
+        return Err(Error::ToolchainNotInstalled(name.to_owned()));
+    }
+
+    fs::write(default_toolchain_path(), name)?;
+
+    println!("Default toolchain set to '{}'", name);
+
+    Ok(())
+}
+
+fn export(name: Option<String>, out_path: Option<PathBuf>) -> Result<()> {
+    let name = name.unwrap_or_else(project_toolchain);
+    let dir = toolchain_dir(&name)?;
+
+    if !dir.exists() {
+        return Err(Error::ToolchainNotInstalled(name));
+    }
+
+    let out_path = out_path.unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", name)));
+
+    println!("Exporting '{}' to '{}'...", name, out_path.display());
+
+    let out = GzEncoder::new(fs::File::create(&out_path)?, Compression::default());
+    let mut archive = tar::Builder::new(out);
+
+    // The toolchain is a full copy, so any symlinks within it are kept as links
+    archive.follow_symlinks(false);
+    archive.append_dir_all(&name, &dir)?;
+    archive.into_inner()?.finish()?;
+
+    println!("{}", format!("Exported '{}'", out_path.display()).green());
+
+    Ok(())
+}
+
+fn info(name: Option<String>) -> Result<()> {
+    let name = name.unwrap_or_else(project_toolchain);
+    let dir = toolchain_dir(&name)?;
+
+    if !dir.exists() {
+        return Err(Error::ToolchainNotInstalled(name));
+    }
+
+    let info = match ToolchainInfo::read(&dir) {
+        Some(info) => info,
+        None => {
+            println!(
+                "{}: '{}' was installed without recording how, reinstall it to record this",
+                "NOTE".bright_blue(),
+                name
+            );
+            return Ok(());
+        }
+    };
+
+    println!("{:<14} {}", "Toolchain:".bold(), name);
+    println!("{:<14} {}", "Repo:".bold(), info.repo);
+    println!("{:<14} {} {}", "Ref:".bold(), info.git_ref_kind, info.git_ref);
+    println!("{:<14} {}", "Commit:".bold(), info.commit);
+    println!("{:<14} {}", "Base nightly:".bold(), info.base_nightly);
+
+    if let Some(mirror) = &info.mirror {
+        println!("{:<14} {}", "Mirror:".bold(), mirror);
+    }
+
+    println!("{:<14} cargo-skyline {}", "Installed by:".bold(), info.cargo_skyline_version);
+
+    match update_std::src_commit(&dir) {
+        Some(commit) if commit != info.commit => println!(
+            "{}: rust-src has since been changed to {}",
+            "WARN".yellow().bold(),
+            commit
+        ),
+        _ => (),
+    }
+
+    let mirror = info
+        .mirror
+        .as_ref()
+        .map(|mirror| format!(" --mirror {}", mirror))
+        .unwrap_or_default();
+
+    println!(
+        "\nTo reproduce:\n  cargo skyline toolchain install {} --repo {} --rev {} --base-nightly {}{}",
+        name, info.repo, info.commit, info.base_nightly, mirror
+    );
+
+    Ok(())
+}
+
+pub fn toolchain_command(command: ToolchainCommand) -> Result<()> {
+    match command {
+        ToolchainCommand::List => list(),
+        ToolchainCommand::Install {
+            name,
+            source,
+            deep,
+            default,
+        } => install(name, source, deep, default),
+        ToolchainCommand::Remove { name } => remove(name),
+        ToolchainCommand::Default { name: Some(name) } => {
+            validate_name(&name)?;
+            set_default(&name)
+        }
+        ToolchainCommand::Default { name: None } => {
+            println!("{}", default_toolchain());
+            Ok(())
+        }
+        ToolchainCommand::Info { name } => info(name),
+        ToolchainCommand::Verify { name } => verify::verify(name),
+        ToolchainCommand::Repair { name } => verify::repair(name),
+        ToolchainCommand::Export { name, out_path } => export(name, out_path),
+    }
+}
@@ -0,0 +1,260 @@
+use super::{is_linked, project_toolchain, toolchain_dir, ToolchainInfo};
+use crate::error::{Error, Result};
+use crate::update_std::{self, StdSource};
+use owo_colors::OwoColorize;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[derive(PartialEq)]
+enum Problem {
+    NotLinked,
+    MissingBinary(&'static str),
+    BrokenBinary(&'static str),
+    MissingSrc,
+    WrongCommit { expected: String, actual: String },
+    Submodules,
+    MissingTargetFiles,
+    Unfinished,
+}
+
+impl Problem {
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Problem::NotLinked => format!("'{}' is not linked with rustup", name),
+            Problem::MissingBinary(binary) => format!("{} is missing", binary),
+            Problem::BrokenBinary(binary) => format!("{} could not be run", binary),
+            Problem::MissingSrc => "the rust-src checkout is missing or incomplete".to_owned(),
+            Problem::WrongCommit { expected, actual } => format!(
+                "rust-src is at {} rather than the recorded commit {}",
+                actual, expected
+            ),
+            Problem::Submodules => "rust-src submodules are not checked out".to_owned(),
+            Problem::MissingTargetFiles => "the target JSON or linker script is missing".to_owned(),
+            Problem::Unfinished => "installing or updating it did not finish".to_owned(),
+        }
+    }
+
+    fn breaks_binaries(&self) -> bool {
+        matches!(self, Problem::MissingBinary(_) | Problem::BrokenBinary(_))
+    }
+}
+
+const BINARIES: [&str; 2] = ["rustc", "cargo"];
+
+/// Written as the last step of installing, updating or repairing a toolchain
+const COMPLETE_MARKER: &str = ".skyline-complete";
+
+pub fn mark_complete(toolchain: &Path) -> Result<()> {
+    fs::write(toolchain.join(COMPLETE_MARKER), "")?;
+
+    Ok(())
+}
+
+/// Remove the completion marker before changing a toolchain in place, so that it's never used if
+/// the change is interrupted
+pub fn mark_incomplete(toolchain: &Path) {
+    let _ = fs::remove_file(toolchain.join(COMPLETE_MARKER));
+}
+
+/// Whether the last install, update or repair of a toolchain finished. Toolchains installed
+/// before anything was recorded in them never had a marker, so are assumed to have finished.
+fn is_finished(toolchain: &Path) -> bool {
+    toolchain.join(COMPLETE_MARKER).exists() || ToolchainInfo::read(toolchain).is_none()
+}
+
+fn binary_path(toolchain: &Path, binary: &str) -> std::path::PathBuf {
+    toolchain
+        .join("bin")
+        .join(format!("{}{}", binary, std::env::consts::EXE_SUFFIX))
+}
+
+fn src_is_complete(toolchain: &Path) -> bool {
+    let src_dir = toolchain.join("lib/rustlib/src/rust");
+
+    src_dir.join("library").is_dir() && update_std::src_commit(toolchain).is_some()
+}
+
+/// Whether any submodule of rust-src is uninitialized or at a different commit than expected
+fn submodules_broken(toolchain: &Path) -> bool {
+    Command::new("git")
+        .current_dir(toolchain.join("lib/rustlib/src/rust"))
+        .args(["submodule", "status", "--recursive"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|line| line.starts_with('-') || line.starts_with('+'))
+        })
+        .unwrap_or(true)
+}
+
+/// Cheap checks for whether a toolchain was only partially installed, run before every build
+pub fn is_complete(name: &str) -> Result<bool> {
+    let toolchain = toolchain_dir(name)?;
+
+    Ok(is_finished(&toolchain)
+        && BINARIES
+            .iter()
+            .all(|binary| binary_path(&toolchain, binary).exists())
+        && toolchain.join("lib/rustlib/src/rust/library").is_dir())
+}
+
+fn check(name: &str, toolchain: &Path) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    if !is_finished(toolchain) {
+        problems.push(Problem::Unfinished);
+    }
+
+    if !is_linked(name)? {
+        problems.push(Problem::NotLinked);
+    }
+
+    for binary in BINARIES {
+        let path = binary_path(toolchain, binary);
+
+        if !path.exists() {
+            problems.push(Problem::MissingBinary(binary));
+            continue;
+        }
+
+        let runs = Command::new(&path)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+
+        if !runs {
+            problems.push(Problem::BrokenBinary(binary));
+        }
+    }
+
+    if !src_is_complete(toolchain) {
+        problems.push(Problem::MissingSrc);
+    } else {
+        let expected = ToolchainInfo::read(toolchain).map(|info| info.commit);
+        let actual = update_std::src_commit(toolchain);
+
+        match (expected, actual) {
+            (Some(expected), Some(actual)) if !expected.is_empty() && expected != actual => {
+                problems.push(Problem::WrongCommit { expected, actual })
+            }
+            _ => (),
+        }
+
+        if submodules_broken(toolchain) {
+            problems.push(Problem::Submodules);
+        }
+    }
+
+    if !update_std::target_json_path().exists() || !update_std::linker_script_path().exists() {
+        problems.push(Problem::MissingTargetFiles);
+    }
+
+    Ok(problems)
+}
+
+fn report(name: &str, problems: &[Problem]) -> Result<()> {
+    for problem in problems {
+        eprintln!("{}: {}", "ERROR".red().bold(), problem.describe(name));
+    }
+
+    if problems.is_empty() {
+        println!("{}", format!("'{}' verified!", name).green());
+        Ok(())
+    } else {
+        Err(Error::ToolchainVerifyFailed(name.to_owned(), problems.len()))
+    }
+}
+
+pub fn verify(name: Option<String>) -> Result<()> {
+    let name = name.unwrap_or_else(project_toolchain);
+    let toolchain = toolchain_dir(&name)?;
+
+    if !toolchain.exists() {
+        return Err(Error::ToolchainNotInstalled(name));
+    }
+
+    report(&name, &check(&name, &toolchain)?)
+}
+
+/// Fix only the parts of a toolchain which are broken, using how it was originally installed
+pub fn repair(name: Option<String>) -> Result<()> {
+    let name = name.unwrap_or_else(project_toolchain);
+    let toolchain = toolchain_dir(&name)?;
+
+    if !toolchain.exists() {
+        return Err(Error::ToolchainNotInstalled(name));
+    }
+
+    let problems = check(&name, &toolchain)?;
+
+    if problems.is_empty() {
+        println!("{}", format!("'{}' has nothing to repair", name).green());
+        return Ok(());
+    }
+
+    let info = ToolchainInfo::read(&toolchain);
+
+    // Without a record of how it was installed, the best that can be done is the default source
+    let source = match &info {
+        Some(info) => StdSource::from_info(info)?,
+        None => {
+            eprintln!(
+                "{}: '{}' was installed without recording how, repairing from the latest std",
+                "WARN".yellow().bold(),
+                name
+            );
+            StdSource::default()
+        }
+    };
+
+    for problem in &problems {
+        println!("Repairing: {}", problem.describe(&name));
+    }
+
+    // Anything could have been left half-copied by an unfinished install, so both the base and
+    // rust-src are restored to what was recorded
+    let unfinished = problems.contains(&Problem::Unfinished);
+
+    if unfinished || problems.iter().any(Problem::breaks_binaries) {
+        update_std::restore_base_toolchain(&source, &toolchain)?;
+    }
+
+    if unfinished {
+        update_std::restore_src(&source, &toolchain)?;
+        let commit = info.as_ref().map(|info| info.commit.as_str());
+        update_std::checkout_src(&toolchain, commit.filter(|commit| !commit.is_empty()))?;
+    } else if problems.contains(&Problem::MissingSrc) {
+        update_std::restore_src(&source, &toolchain)?;
+    } else if problems.iter().any(|problem| matches!(problem, Problem::WrongCommit { .. })) {
+        let commit = info.as_ref().map(|info| info.commit.as_str());
+        update_std::checkout_src(&toolchain, commit)?;
+    } else if problems.contains(&Problem::Submodules) {
+        update_std::checkout_src(&toolchain, None)?;
+    }
+
+    if problems.contains(&Problem::MissingTargetFiles) {
+        update_std::ensure_target_json_exists();
+    }
+
+    if problems.contains(&Problem::NotLinked) {
+        update_std::rustup_toolchain_link(&name, &toolchain)?;
+    }
+
+    let problems: Vec<_> = check(&name, &toolchain)?
+        .into_iter()
+        .filter(|problem| *problem != Problem::Unfinished)
+        .collect();
+
+    if problems.is_empty() {
+        mark_complete(&toolchain)?;
+    }
+
+    report(&name, &problems)
+}
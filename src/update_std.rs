@@ -1,11 +1,19 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 use crate::build::get_rustup_home;
+use crate::cargo_info::Metadata;
+use crate::prompt;
+use owo_colors::OwoColorize;
+use crate::toolchain::{self, ToolchainInfo};
 use crate::Error;
 
+use flate2::read::GzDecoder;
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::{env, fs};
+use structopt::StructOpt;
 
 fn get_cargo_dir() -> PathBuf {
     env::var("CARGO_HOME")
@@ -18,22 +26,178 @@ fn get_cargo_dir() -> PathBuf {
         .ensure_exists()
 }
 
-const ORG: &str = "skyline-rs";
-const REPO: &str = "rust-src";
+pub const DEFAULT_REPO: &str = "skyline-rs/rust-src";
 const BRANCH: &str = "skyline";
+const MIRROR_ENV: &str = "SKYLINE_RUST_SRC_MIRROR";
+
+/// The branch, tag or commit of the std fork to install
+#[derive(Clone)]
+pub enum GitRef {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl GitRef {
+    fn name(&self) -> &str {
+        match self {
+            GitRef::Branch(name) | GitRef::Tag(name) | GitRef::Rev(name) => name,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            GitRef::Branch(_) => "branch",
+            GitRef::Tag(_) => "tag",
+            GitRef::Rev(_) => "rev",
+        }
+    }
+}
+
+/// Where to clone the custom standard library from, a GitHub repo in the form of `{user}/{repo}`
+#[derive(Clone)]
+pub struct StdSource {
+    owner: String,
+    repo: String,
+    git_ref: GitRef,
+    mirror: Option<String>,
+    base_nightly: Option<String>,
+}
+
+impl StdSource {
+    pub fn new(repo: &str, git_ref: Option<GitRef>) -> Result<Self, Error> {
+        let (owner, repo) = repo
+            .trim_end_matches(".git")
+            .split_once('/')
+            .filter(|(owner, repo)| !owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+            .ok_or(Error::InvalidRepo)?;
+
+        Ok(Self {
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            git_ref: git_ref.unwrap_or_else(|| GitRef::Branch(BRANCH.to_owned())),
+            mirror: std::env::var(MIRROR_ENV).ok(),
+            base_nightly: None,
+        })
+    }
+
+    /// The source a toolchain was installed from, pinned to the exact commit it was at
+    pub(crate) fn from_info(info: &ToolchainInfo) -> Result<Self, Error> {
+        let mut source = Self::new(&info.repo, Some(GitRef::Rev(info.commit.clone())))?;
+
+        if info.mirror.is_some() {
+            source.mirror = info.mirror.clone();
+        }
+
+        source.base_nightly = Some(info.base_nightly.clone());
+
+        Ok(source)
+    }
+
+    /// The git url to clone from, preferring a mirror if one is configured
+    fn url(&self) -> String {
+        self.mirror
+            .clone()
+            .unwrap_or_else(|| format!("https://github.com/{}/{}", self.owner, self.repo))
+    }
+}
+
+#[derive(StructOpt)]
+pub struct SourceArgs {
+    #[structopt(
+        short,
+        long,
+        default_value = DEFAULT_REPO,
+        about = "GitHub repo of the std fork to install, in the form of {user}/{repo}"
+    )]
+    repo: String,
+
+    #[structopt(short, long, conflicts_with_all = &["branch", "rev"], about = "Tag of the std fork to install")]
+    tag: Option<String>,
+
+    #[structopt(short, long, conflicts_with = "rev", about = "Branch of the std fork to install, defaults to `skyline`")]
+    branch: Option<String>,
+
+    #[structopt(long, about = "Commit of the std fork to install")]
+    rev: Option<String>,
+
+    #[structopt(
+        long,
+        about = "Git url to clone the std fork from instead of GitHub, defaults to $SKYLINE_RUST_SRC_MIRROR"
+    )]
+    mirror: Option<String>,
+
+    #[structopt(
+        long,
+        about = "Nightly to base the toolchain on, such as `nightly-2024-03-01`, rather than looking it up on GitHub"
+    )]
+    base_nightly: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &["tag", "branch", "rev", "mirror", "base-nightly"],
+        about = "Install from a tarball created by `cargo skyline toolchain export` rather than downloading"
+    )]
+    from_archive: Option<PathBuf>,
+}
+
+impl SourceArgs {
+    pub fn into_source(self) -> Result<StdSource, Error> {
+        let git_ref = self
+            .tag
+            .map(GitRef::Tag)
+            .or(self.branch.map(GitRef::Branch))
+            .or(self.rev.map(GitRef::Rev));
+
+        let mut source = StdSource::new(&self.repo, git_ref)?;
+
+        if self.mirror.is_some() {
+            source.mirror = self.mirror;
+        }
+
+        source.base_nightly = self.base_nightly.map(|nightly| {
+            if nightly.starts_with("nightly-") {
+                nightly
+            } else {
+                format!("nightly-{}", nightly)
+            }
+        });
+
+        Ok(source)
+    }
+}
+
+/// Install a toolchain, either from an archive or by building it from the given source
+pub fn install_toolchain(name: &str, source: SourceArgs, deep: bool) -> Result<(), Error> {
+    match &source.from_archive {
+        Some(archive) => install_from_archive(name, archive),
+        None => create_modified_toolchain(name, &source.into_source()?, deep, false),
+    }
+}
+
+impl Default for StdSource {
+    fn default() -> Self {
+        let (owner, repo) = DEFAULT_REPO.split_once('/').unwrap();
 
-fn url() -> String {
-    format!("https://github.com/{}/{}", ORG, REPO)
+        Self {
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            git_ref: GitRef::Branch(BRANCH.to_owned()),
+            mirror: std::env::var(MIRROR_ENV).ok(),
+            base_nightly: None,
+        }
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
-async fn get_base_nightly() -> Result<String, Error> {
+async fn get_base_nightly(source: StdSource) -> Result<String, Error> {
     let octocrab = octocrab::instance();
 
     let commit = octocrab
-        .repos(ORG, REPO)
+        .repos(&source.owner, &source.repo)
         .list_commits()
-        .branch(BRANCH)
+        .sha(source.git_ref.name())
         .author("bors")
         .per_page(1)
         .send()
@@ -55,50 +219,92 @@ async fn get_base_nightly() -> Result<String, Error> {
 }
 
 fn get_original_toolchain(
+    source: &StdSource,
     base_nightly_progress: &ProgressBar,
     progress: &ProgressBar,
     success_style: ProgressStyle,
     failed_style: ProgressStyle,
-) -> Result<PathBuf, Error> {
-    let base_nightly = std::thread::spawn(get_base_nightly);
+) -> Result<(PathBuf, String), Error> {
+    let base_nightly = find_base_nightly(
+        source,
+        base_nightly_progress,
+        progress,
+        success_style.clone(),
+        failed_style.clone(),
+    )?;
 
-    while !base_nightly.is_finished() {
-        base_nightly_progress.tick();
-    }
+    let toolchain = install_base_toolchain(&base_nightly, progress, success_style, failed_style)?;
+
+    Ok((toolchain, base_nightly))
+}
+
+/// Get the nightly the std fork is based on, either as given or by looking it up on GitHub
+fn find_base_nightly(
+    source: &StdSource,
+    base_nightly_progress: &ProgressBar,
+    progress: &ProgressBar,
+    success_style: ProgressStyle,
+    failed_style: ProgressStyle,
+) -> Result<String, Error> {
+    let base_nightly = match &source.base_nightly {
+        Some(base_nightly) => Ok(base_nightly.clone()),
+        None => {
+            let source = source.clone();
+            let base_nightly = std::thread::spawn(move || get_base_nightly(source));
+
+            while !base_nightly.is_finished() {
+                base_nightly_progress.tick();
+            }
 
-    let base_nightly = base_nightly.join().unwrap().map_err(|err| {
+            base_nightly.join().unwrap()
+        }
+    };
+
+    let base_nightly = base_nightly.inspect_err(|err| {
         base_nightly_progress.set_style(failed_style.clone());
 
-        if let Error::GithubError(oct_err) = &err {
+        if let Error::GithubError(oct_err) = err {
             if let octocrab::Error::GitHub {
                 source,
                 backtrace: _,
             } = oct_err {
-                base_nightly_progress.finish_with_message(format!("Failed to get find base nightly: {}", source.message))
+                finish_step(base_nightly_progress, format!("Failed to get find base nightly: {}", source.message))
             }
         } else {
-            base_nightly_progress.finish_with_message("Failed to get find base nightly");
+            finish_step(base_nightly_progress, "Failed to get find base nightly");
         }
-
-        err
     })?;
 
+    let using_message = format!("Using {base_nightly} as a base for installation...");
+
+    if prompt::is_tty() {
+        progress.println(using_message);
+    } else {
+        println!("{}", using_message);
+    }
+
+    base_nightly_progress.set_style(success_style);
+    finish_step(base_nightly_progress, "Base nightly found");
+
+    Ok(base_nightly)
+}
+
+/// Install the base nightly with rustup if it isn't already, returning where it is installed
+fn install_base_toolchain(
+    base_nightly: &str,
+    progress: &ProgressBar,
+    success_style: ProgressStyle,
+    failed_style: ProgressStyle,
+) -> Result<PathBuf, Error> {
     let toolchain = get_rustup_home()?
         .push_join("toolchains")
         .push_join(format!("{}-{}", base_nightly, TARGET));
 
-    progress.println(format!(
-        "Using {base_nightly} as a base for installation..."
-    ));
-
-    base_nightly_progress.set_style(success_style.clone());
-    base_nightly_progress.finish_with_message("Base nightly found");
-
     if toolchain.exists() {
         Ok(toolchain)
     } else {
         let mut rustup_cmd = Command::new("rustup")
-            .args(&["toolchain", "add", &base_nightly])
+            .args(["toolchain", "add", base_nightly])
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .stdin(Stdio::null())
@@ -119,31 +325,73 @@ fn get_original_toolchain(
 
         if install_succeed {
             progress.set_style(success_style);
-            progress.finish_with_message("Base toolchain downloaded");
+            finish_step(progress, "Base toolchain downloaded");
         } else {
             progress.set_style(failed_style);
-            progress.finish_with_message("Failed to get find base nightly");
+            finish_step(progress, "Failed to get find base nightly");
         }
 
         (install_succeed && toolchain.exists())
-            .then(|| toolchain)
+            .then_some(toolchain)
             .ok_or(Error::RustupToolchainAddFailed)
     }
 }
 
-fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
-    fs::create_dir_all(&dst)?;
+/// Materialize a file from the base toolchain as cheaply as possible: a reflink where the
+/// filesystem supports it, otherwise a hard link, falling back to a full copy
+fn link_or_copy(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let _ = fs::remove_file(dst);
+
+    if reflink_copy::reflink(src, dst).is_ok() || fs::hard_link(src, dst).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(src, dst).map(|_| ())
+}
+
+/// Copy a directory, leaving out `skip` and everything within it. Files are linked rather than
+/// copied where possible, as the base toolchain is hundreds of megabytes.
+fn copy_dir_except(src: &Path, dst: &Path, skip: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dst)?;
 
     for entry in fs::read_dir(src)? {
         let entry = entry?;
-        let ty = entry.file_type()?;
 
-        if ty.is_dir() {
-            copy_dir_all(entry.path(), dst.as_ref().join(entry.file_name()))?;
+        if entry.path() == skip {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            copy_dir_except(&entry.path(), &dst.join(entry.file_name()), skip)?;
+        } else {
+            link_or_copy(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Empty a directory of everything but `keep` and the directories leading to it
+fn remove_dir_except(dir: &Path, keep: &Path) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path == keep {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            if keep.starts_with(&path) {
+                remove_dir_except(&path, keep)?;
+            } else {
+                fs::remove_dir_all(&path)?;
+            }
         } else {
-            fs::copy(entry.path(), dst.as_ref().join(entry.file_name()))?;
+            fs::remove_file(&path)?;
         }
     }
+
     Ok(())
 }
 
@@ -151,13 +399,13 @@ pub fn target_json_path() -> PathBuf {
     get_cargo_skyline_dir().push_join("aarch64-skyline-switch.json")
 }
 
-fn linker_script_path() -> PathBuf {
+pub(crate) fn linker_script_path() -> PathBuf {
     get_cargo_skyline_dir().push_join("link.T")
 }
 
 const LINKER_SCRIPT: &str = include_str!("link.T");
 
-fn ensure_target_json_exists() {
+pub(crate) fn ensure_target_json_exists() {
     let target_json_path = target_json_path();
 
     let link_script_path = linker_script_path();
@@ -167,19 +415,124 @@ fn ensure_target_json_exists() {
 
     // previously checked to make sure it didn't already exist, but maybe let's just always keep it
     // up to date?
-    fs::write(target_json_path, target_json())
+    fs::write(target_json_path, target_json(&linker_script_path()))
         .expect("Failed to create aarch64-skyline-switch target json");
 }
 
-fn target_json() -> String {
+/// Recursively merge `overrides` onto `base`, with objects being merged key-by-key and any other
+/// value replacing what was there before
+pub(crate) fn merge_json(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(key) {
+                    Some(base_value) => merge_json(base_value, value),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides.clone(),
+    }
+}
+
+/// Linker argument keys of the target JSON which are appended to rather than replaced, so that
+/// overrides can't drop the arguments every plugin needs to link correctly
+const APPENDED_TARGET_KEYS: [&str; 2] = ["pre-link-args", "post-link-args"];
+
+/// Merge `target-overrides` onto a target JSON, appending any linker arguments for a linker
+/// flavor to the existing ones
+fn merge_target_overrides(target_json: &mut serde_json::Value, overrides: &serde_json::Value) {
+    let mut overrides = overrides.clone();
+
+    for key in APPENDED_TARGET_KEYS {
+        if let (Some(base), Some(serde_json::Value::Object(extra))) =
+            (target_json.get(key), overrides.get_mut(key))
+        {
+            for (flavor, args) in extra.iter_mut() {
+                if let (Some(serde_json::Value::Array(base_args)), serde_json::Value::Array(args)) =
+                    (base.get(flavor), args)
+                {
+                    args.splice(0..0, base_args.iter().cloned());
+                }
+            }
+        }
+    }
+
+    merge_json(target_json, &overrides);
+}
+
+fn read_target_file(path: &Path) -> Result<String, Error> {
+    fs::read_to_string(path).map_err(|_| Error::TargetFileMissing(path.to_owned()))
+}
+
+fn write_if_changed(path: &Path, contents: &str) -> Result<(), Error> {
+    if fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        fs::write(path, contents)?;
+    }
+
+    Ok(())
+}
+
+/// Get the target JSON to build the current project with. Projects which customize their target
+/// JSON or linker script get their own copies under the target directory, everything else shares
+/// the default one.
+pub fn project_target_json_path(metadata: &Metadata) -> Result<PathBuf, Error> {
+    let is_customized = metadata.target_json_path.is_some()
+        || metadata.target_overrides.is_some()
+        || metadata.linker_script_path.is_some()
+        || metadata.extra_linker_script_path.is_some();
+
+    if !is_customized {
+        return Ok(target_json_path());
+    }
+
+    let dir = metadata.target_dir.join("skyline").ensure_exists();
+
+    let mut linker_script = match &metadata.linker_script_path {
+        Some(path) => read_target_file(path)?,
+        None => LINKER_SCRIPT.to_owned(),
+    };
+
+    if let Some(path) = &metadata.extra_linker_script_path {
+        linker_script.push('\n');
+        linker_script.push_str(&read_target_file(path)?);
+    }
+
+    let linker_script_path = dir.join("link.T");
+    write_if_changed(&linker_script_path, &linker_script)?;
+
+    let target_json = match &metadata.target_json_path {
+        Some(path) => read_target_file(path)?,
+        None => target_json(&linker_script_path),
+    };
+
+    let mut target_json: serde_json::Value =
+        serde_json::from_str(&target_json).map_err(|_| Error::BadTargetJson)?;
+
+    if let Some(overrides) = &metadata.target_overrides {
+        merge_target_overrides(&mut target_json, overrides);
+    }
+
+    let target_json_path = dir.join("aarch64-skyline-switch.json");
+    write_if_changed(
+        &target_json_path,
+        &serde_json::to_string_pretty(&target_json).unwrap(),
+    )?;
+
+    Ok(target_json_path)
+}
+
+fn target_json(linker_script_path: &Path) -> String {
     let linker_script = if cfg!(windows) {
-        linker_script_path()
+        linker_script_path
             .to_str()
             .unwrap()
             .replace('\\', "/")
             .into()
     } else {
-        linker_script_path()
+        linker_script_path.to_owned()
     };
 
     format!(
@@ -222,7 +575,155 @@ fn target_json() -> String {
     )
 }
 
-pub fn create_modified_toolchain(deep: bool, pull: bool) -> Result<(), Error> {
+/// Run git to completion, ticking the progress bar while waiting
+fn run_git(dir: &Path, args: &[&str], progress: &ProgressBar) -> Result<bool, Error> {
+    let mut git_cmd = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|_| Error::GitNotInstalled)?;
+
+    let status = loop {
+        progress.tick();
+
+        if let Some(status) = git_cmd.try_wait()? {
+            break status;
+        }
+    };
+
+    Ok(status.success())
+}
+
+/// Update an existing clone of the std fork to the requested ref, fetching only what's needed.
+/// Shallow clones stay shallow unless a deep clone is requested.
+fn update_src(
+    source: &StdSource,
+    src_dir: &Path,
+    deep: bool,
+    progress: &ProgressBar,
+) -> Result<bool, Error> {
+    let url = source.url();
+    let is_shallow = src_dir.join(".git/shallow").exists();
+
+    let depth: &[&str] = match (is_shallow, deep) {
+        (true, true) => &["--unshallow"],
+        (true, false) => &["--depth", "1"],
+        (false, _) => &[],
+    };
+
+    let submodule_depth: &[&str] = if deep { &[] } else { &["--depth", "1"] };
+
+    let steps: [&[&str]; 4] = [
+        &["remote", "set-url", "origin", &url],
+        &[&["fetch", "-q"][..], depth, &["origin", source.git_ref.name()]].concat(),
+        &["checkout", "-q", "FETCH_HEAD"],
+        &[&["submodule", "update", "--init", "--recursive"][..], submodule_depth].concat(),
+    ];
+
+    for step in steps {
+        if !run_git(src_dir, step, progress)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+/// Print what an update changed, given how the toolchain was installed before it
+fn print_changes(name: &str, previous: Option<&ToolchainInfo>, current: &ToolchainInfo) {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => {
+            println!(
+                "Installed '{}' at {} based on {}",
+                name,
+                short_commit(&current.commit),
+                current.base_nightly
+            );
+            return;
+        }
+    };
+
+    let change = |label: &str, old: &str, new: &str| {
+        if old == new {
+            println!("{:<14} {} (unchanged)", label.bold(), new);
+        } else {
+            println!("{:<14} {} → {}", label.bold(), old, new.green());
+        }
+    };
+
+    println!("Updated '{}'", name);
+    change(
+        "rust-src:",
+        short_commit(&previous.commit),
+        short_commit(&current.commit),
+    );
+    change("Base nightly:", &previous.base_nightly, &current.base_nightly);
+}
+
+/// Clone the std fork into `src_dir`. Branches and tags can be cloned directly, commits have to be
+/// fetched by hash into an empty repo.
+fn clone_std(
+    source: &StdSource,
+    src_dir: &Path,
+    deep: bool,
+    progress: &ProgressBar,
+) -> Result<bool, Error> {
+    let url = source.url();
+    let shallow: &[&str] = if deep { &[] } else { &["--depth", "1"] };
+
+    match &source.git_ref {
+        GitRef::Branch(name) | GitRef::Tag(name) => {
+            let parent = src_dir.parent().unwrap();
+            let shallow_submodules: &[&str] = if deep { &[] } else { &["--shallow-submodules"] };
+
+            run_git(
+                parent,
+                &[
+                    &["clone", "--recurse-submodules"][..],
+                    shallow_submodules,
+                    shallow,
+                    &["--branch", name, &url, src_dir.to_str().unwrap()],
+                ]
+                .concat(),
+                progress,
+            )
+        }
+        GitRef::Rev(rev) => {
+            fs::create_dir_all(src_dir)?;
+
+            let steps: [&[&str]; 5] = [
+                &["init", "-q"],
+                &["remote", "add", "origin", &url],
+                &[&["fetch"][..], shallow, &["origin", rev]].concat(),
+                &["checkout", "-q", "FETCH_HEAD"],
+                &[&["submodule", "update", "--init", "--recursive"][..], shallow].concat(),
+            ];
+
+            for step in steps {
+                if !run_git(src_dir, step, progress)? {
+                    return Ok(false);
+                }
+            }
+
+            Ok(true)
+        }
+    }
+}
+
+pub fn create_modified_toolchain(
+    name: &str,
+    source: &StdSource,
+    deep: bool,
+    pull: bool,
+) -> Result<(), Error> {
     let multiprogress = MultiProgress::new();
     let style =
         ProgressStyle::default_spinner().template("{prefix:.bold.dim} {spinner} {wide_msg}").expect("Could not create a ProgressStyle");
@@ -254,135 +755,348 @@ pub fn create_modified_toolchain(deep: bool, pull: bool) -> Result<(), Error> {
 
     multiprogress.clear().expect("Could not clear the MultiProgress instance");
 
-    let toolchain = get_toolchain();
+    let toolchain = toolchain::toolchain_dir(name)?;
 
     if pull {
+        toolchain::mark_incomplete(&toolchain);
+
         let pull_success = Command::new("git")
             .current_dir(toolchain.join("lib/rustlib/src/rust"))
-            .args(&["pull", "--recurse-submodules", "-q"])
+            .args(["pull", "--recurse-submodules", "-q"])
             .status()
             .map_err(|_| Error::GitNotInstalled)?
             .success();
 
         return if pull_success {
-            Ok(())
+            // Keep the recorded commit in sync with what was pulled
+            if let Some(mut info) = ToolchainInfo::read(&toolchain) {
+                if let Some(commit) = src_commit(&toolchain) {
+                    info.commit = commit;
+                    info.write(&toolchain)?;
+                }
+            }
+
+            toolchain::mark_complete(&toolchain)
         } else {
             Err(Error::StdCloneFailed)
         };
     }
 
-    let _ = fs::remove_dir_all(&toolchain);
+    // Only complete toolchains are updated in place, anything else is reinstalled from scratch
+    let previous = ToolchainInfo::read(&toolchain);
+    let reuse = previous.is_some() && toolchain::is_complete(name)?;
+
+    if reuse {
+        toolchain::mark_incomplete(&toolchain);
+    } else {
+        let _ = fs::remove_dir_all(&toolchain);
+    }
 
-    let original_toolchain = get_original_toolchain(
+    let base_nightly = find_base_nightly(
+        source,
         &get_base_nightly_pb,
         &base_chain_pb,
         finished_style.clone(),
         failed_style.clone(),
     )?;
 
-    copy_dir_all(&original_toolchain, &toolchain).map_err(|_| Error::ToolchainCopyFailed)?;
-
-    let src_dir = toolchain.join("lib/rustlib/src");
-
-    if src_dir.exists() {
-        let _ = fs::remove_dir_all(&src_dir);
-    }
+    let src_parent = toolchain.join("lib/rustlib/src");
+    let base_changed = !reuse
+        || previous.as_ref().map(|info| &info.base_nightly) != Some(&base_nightly);
+
+    // An unchanged base is reused as-is, so the nightly doesn't need to be installed again
+    if base_changed {
+        let original_toolchain = install_base_toolchain(
+            &base_nightly,
+            &base_chain_pb,
+            finished_style.clone(),
+            failed_style.clone(),
+        )?;
+
+        if reuse {
+            remove_dir_except(&toolchain, &src_parent).map_err(|_| Error::ToolchainCopyFailed)?;
+        }
 
-    if fs::create_dir_all(&src_dir).is_err() {
-        panic!("Failed to create {:?}", &src_dir);
+        copy_dir_except(
+            &original_toolchain,
+            &toolchain,
+            &original_toolchain.join("lib/rustlib/src"),
+        )
+        .map_err(|_| Error::ToolchainCopyFailed)?;
+    } else {
+        base_chain_pb.set_style(finished_style.clone());
+        finish_step(&base_chain_pb, "Base toolchain unchanged");
     }
 
-    let src_dir = src_dir.push_join("rust");
-
-    let mut clone_cmd = Command::new("git")
-        .args(&["clone", "--recurse-submodules"])
-        .args(if deep {
-            &[]
-        } else {
-            &["--shallow-submodules", "--depth", "1"][..]
-        })
-        .arg("--branch")
-        .arg(BRANCH)
-        .arg(url())
-        .arg(&src_dir)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .stdin(Stdio::null())
-        .spawn()
-        .map_err(|_| Error::GitNotInstalled)?;
+    let src_dir = src_parent.join("rust");
 
-    let clone_status = loop {
-        std_clone_pb.tick();
+    let clone_success = if reuse && src_commit(&toolchain).is_some() {
+        update_src(source, &src_dir, deep, &std_clone_pb)?
+    } else {
+        let _ = fs::remove_dir_all(&src_parent);
 
-        if let Some(status) = clone_cmd.try_wait()? {
-            break status;
+        if fs::create_dir_all(&src_parent).is_err() {
+            panic!("Failed to create {:?}", &src_parent);
         }
+
+        clone_std(source, &src_dir, deep, &std_clone_pb)?
     };
 
-    std_clone_pb.set_style(if clone_status.success() {
+    std_clone_pb.set_style(if clone_success {
         finished_style
     } else {
         failed_style
     });
-    std_clone_pb.finish_with_message(if clone_status.success() {
+    finish_step(&std_clone_pb, if clone_success {
         "Finished downloading custom Rust standard library"
     } else {
         "Failed to download custom Rust standard library"
     });
 
-    rustup_toolchain_link("skyline-v3", &toolchain)?;
+    rustup_toolchain_link(name, &toolchain)?;
+
+    if clone_success {
+        let info = ToolchainInfo {
+            repo: format!("{}/{}", source.owner, source.repo),
+            git_ref_kind: source.git_ref.kind().to_owned(),
+            git_ref: source.git_ref.name().to_owned(),
+            commit: src_commit(&toolchain).unwrap_or_default(),
+            base_nightly,
+            mirror: source.mirror.clone(),
+            cargo_skyline_version: env!("CARGO_PKG_VERSION").to_owned(),
+        };
+
+        info.write(&toolchain)?;
+        toolchain::mark_complete(&toolchain)?;
+        print_changes(name, previous.as_ref(), &info);
 
-    if clone_status.success() {
         Ok(())
     } else {
         Err(Error::StdCloneFailed)
     }
 }
 
-fn get_cargo_skyline_dir() -> PathBuf {
-    get_cargo_dir().push_join("skyline").ensure_exists()
+/// Finish a progress bar, also printing its message as plain text when there's no terminal for
+/// progress to be drawn to
+fn finish_step(progress: &ProgressBar, message: impl Into<Cow<'static, str>>) {
+    let message = message.into();
+
+    if !prompt::is_tty() {
+        match progress.prefix() {
+            prefix if prefix.is_empty() => eprintln!("{}", message),
+            prefix => eprintln!("{} {}", prefix, message),
+        }
+    }
+
+    progress.finish_with_message(message);
 }
 
-fn get_skyline_toolchain_dir() -> PathBuf {
-    get_cargo_skyline_dir()
-        .push_join("toolchain")
-        .ensure_exists()
+fn spinner(message: &'static str) -> ProgressBar {
+    ProgressBar::new_spinner()
+        .with_message(message)
+        .with_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner} {wide_msg}")
+                .expect("Could not create a ProgressStyle"),
+        )
 }
 
-fn get_toolchain() -> PathBuf {
-    get_skyline_toolchain_dir()
-        .push_join("skyline")
-        .ensure_exists()
+fn finish_spinner(progress: &ProgressBar, success: bool, finished: &'static str, failed: &'static str) {
+    let template = if success { "✔️ {wide_msg}" } else { "❌ {wide_msg}" };
+
+    progress.set_style(
+        ProgressStyle::default_spinner()
+            .template(template)
+            .expect("Could not create a ProgressStyle"),
+    );
+    finish_step(progress, if success { finished } else { failed });
+}
+
+/// Copy the base nightly back over a toolchain, leaving its rust-src checkout alone
+pub(crate) fn restore_base_toolchain(source: &StdSource, toolchain: &Path) -> Result<(), Error> {
+    let multiprogress = MultiProgress::new();
+    let base_nightly_pb = multiprogress.add(spinner("Searching git history for base nightly"));
+    let base_chain_pb = multiprogress.add(spinner("Downloading base toolchain"));
+
+    let finished_style =
+        ProgressStyle::default_spinner().template("✔️ {wide_msg}").expect("Could not create a ProgressStyle");
+    let failed_style =
+        ProgressStyle::default_spinner().template("❌ {wide_msg}").expect("Could not create a ProgressStyle");
+
+    let (original_toolchain, _) = get_original_toolchain(
+        source,
+        &base_nightly_pb,
+        &base_chain_pb,
+        finished_style,
+        failed_style,
+    )?;
+
+    copy_dir_except(
+        &original_toolchain,
+        toolchain,
+        &original_toolchain.join("lib/rustlib/src"),
+    )
+    .map_err(|_| Error::ToolchainCopyFailed)
+}
+
+/// Replace the rust-src checkout of a toolchain with a fresh clone
+pub(crate) fn restore_src(source: &StdSource, toolchain: &Path) -> Result<(), Error> {
+    let src_dir = toolchain.join("lib/rustlib/src/rust");
+    let _ = fs::remove_dir_all(&src_dir);
+    fs::create_dir_all(src_dir.parent().unwrap())?;
+
+    let progress = spinner("Downloading custom Rust standard library");
+    let success = clone_std(source, &src_dir, false, &progress)?;
+
+    finish_spinner(
+        &progress,
+        success,
+        "Finished downloading custom Rust standard library",
+        "Failed to download custom Rust standard library",
+    );
+
+    success.then_some(()).ok_or(Error::StdCloneFailed)
+}
+
+/// Check out the given commit of rust-src along with its submodules, fetching it if needed
+pub(crate) fn checkout_src(toolchain: &Path, commit: Option<&str>) -> Result<(), Error> {
+    let src_dir = toolchain.join("lib/rustlib/src/rust");
+    let progress = spinner("Checking out custom Rust standard library");
+
+    let mut success = true;
+
+    if let Some(commit) = commit {
+        let object = format!("{}^{{commit}}", commit);
+
+        if !run_git(&src_dir, &["cat-file", "-e", &object], &progress)? {
+            success = run_git(&src_dir, &["fetch", "--depth", "1", "origin", commit], &progress)?;
+        }
+
+        success = success && run_git(&src_dir, &["checkout", "-q", commit], &progress)?;
+    }
+
+    success = success
+        && run_git(
+            &src_dir,
+            &["submodule", "update", "--init", "--recursive", "--depth", "1"],
+            &progress,
+        )?;
+
+    finish_spinner(
+        &progress,
+        success,
+        "Finished checking out custom Rust standard library",
+        "Failed to check out custom Rust standard library",
+    );
+
+    success.then_some(()).ok_or(Error::StdCloneFailed)
+}
+
+/// The commit the rust-src checkout of a toolchain is at
+pub(crate) fn src_commit(toolchain: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .current_dir(toolchain.join("lib/rustlib/src/rust"))
+        .args(["rev-parse", "HEAD"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+pub(crate) fn get_cargo_skyline_dir() -> PathBuf {
+    get_cargo_dir().push_join("skyline").ensure_exists()
 }
 
 const TARGET: &str = env!("TARGET");
 
-pub fn check_std_installed() -> Result<(), Error> {
+pub fn check_std_installed(name: &str) -> Result<(), Error> {
     ensure_target_json_exists();
 
-    if get_rustup_home()?
-        .push_join("toolchains/skyline-v3")
-        .exists()
-    {
-        Ok(())
+    if toolchain::is_linked(name)? {
+        // An install which failed partway through still leaves the toolchain linked
+        if toolchain::is_complete(name)? {
+            Ok(())
+        } else {
+            Err(Error::ToolchainIncomplete(name.to_owned()))
+        }
+    } else if name != toolchain::DEFAULT_TOOLCHAIN {
+        // Pinned toolchains are usually a specific version, so installing the latest in their
+        // place would be misleading
+        Err(Error::ToolchainNotInstalled(name.to_owned()))
     } else {
-        let should_install = dialoguer::Confirm::new()
-            .with_prompt("The skyline-rs toolchain is not installed. Would you like to install it?")
-            .default(true)
-            .interact()
-            .unwrap();
+        let should_install = prompt::confirm(
+            "The skyline-rs toolchain is not installed. Would you like to install it?",
+        )?;
 
         if should_install {
-            create_modified_toolchain(false, false)
+            create_modified_toolchain(name, &StdSource::default(), false, false)
         } else {
             std::process::exit(1);
         }
     }
 }
 
-fn rustup_toolchain_link(name: &str, path: &Path) -> Result<(), Error> {
+/// Install a toolchain exported with `cargo skyline toolchain export`, which holds a single
+/// directory containing the toolchain. Both gzipped and plain tarballs are accepted.
+fn install_from_archive(name: &str, archive: &Path) -> Result<(), Error> {
+    let toolchain = toolchain::toolchain_dir(name)?;
+    let unpack_dir = toolchain::toolchains_dir().push_join(format!(".{}.unpacking", name));
+
+    let mut file = fs::File::open(archive).map_err(|_| Error::BadToolchainArchive)?;
+    let mut magic = [0; 2];
+    let is_gzip = file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+    file.seek(SeekFrom::Start(0))?;
+
+    let reader: Box<dyn Read> = if is_gzip {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+
+    let _ = fs::remove_dir_all(&unpack_dir);
+
+    println!("Unpacking '{}'...", archive.display());
+
+    let unpacked = tar::Archive::new(reader)
+        .unpack(&unpack_dir)
+        .map_err(|_| Error::BadToolchainArchive)
+        .and_then(|_| {
+            let mut entries = fs::read_dir(&unpack_dir)?.collect::<Result<Vec<_>, _>>()?;
+
+            match (entries.pop(), entries.is_empty()) {
+                (Some(entry), true) if entry.file_type()?.is_dir() => Ok(entry.path()),
+                _ => Err(Error::BadToolchainArchive),
+            }
+        });
+
+    let unpacked = match unpacked {
+        Ok(unpacked) => unpacked,
+        Err(err) => {
+            let _ = fs::remove_dir_all(&unpack_dir);
+            return Err(err);
+        }
+    };
+
+    let _ = fs::remove_dir_all(&toolchain);
+    fs::rename(&unpacked, &toolchain)?;
+    let _ = fs::remove_dir_all(&unpack_dir);
+
+    rustup_toolchain_link(name, &toolchain)?;
+    toolchain::mark_complete(&toolchain)?;
+
+    println!("Installed toolchain '{}'", name);
+
+    Ok(())
+}
+
+pub(crate) fn rustup_toolchain_link(name: &str, path: &Path) -> Result<(), Error> {
     let status = Command::new("rustup")
-        .args(&["toolchain", "link", name])
+        .args(["toolchain", "link", name])
         .arg(path)
         .stdin(Stdio::null())
         .stdout(Stdio::null())
@@ -397,8 +1111,22 @@ fn rustup_toolchain_link(name: &str, path: &Path) -> Result<(), Error> {
     }
 }
 
-pub fn update_std(_repo: &str, _tag: Option<&str>, deep: bool, pull: bool) -> Result<(), Error> {
-    create_modified_toolchain(deep, pull)?;
+pub fn update_std(
+    source: SourceArgs,
+    name: Option<String>,
+    deep: bool,
+    pull: bool,
+) -> Result<(), Error> {
+    let name = match name {
+        Some(name) => name,
+        None => toolchain::default_toolchain(),
+    };
+
+    if pull {
+        create_modified_toolchain(&name, &source.into_source()?, deep, pull)?;
+    } else {
+        install_toolchain(&name, source, deep)?;
+    }
 
     Ok(())
 }
@@ -406,7 +1134,6 @@ pub fn update_std(_repo: &str, _tag: Option<&str>, deep: bool, pull: bool) -> Re
 pub(crate) trait PathExt: Sized {
     fn ensure_exists(self) -> Self;
     fn push_join<P: AsRef<Path>>(self, join: P) -> Self;
-    fn if_exists(self) -> Option<Self>;
 }
 
 impl PathExt for PathBuf {
@@ -423,12 +1150,4 @@ impl PathExt for PathBuf {
 
         self
     }
-
-    fn if_exists(self) -> Option<Self> {
-        if self.exists() {
-            Some(self)
-        } else {
-            None
-        }
-    }
 }